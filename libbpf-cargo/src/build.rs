@@ -44,6 +44,9 @@ fn extract_version(output: &str) -> Result<&str> {
     )
 }
 
+/// BPF-side headers of libbpf-rs features not provided by libbpf, e.g. `<bpf/usdt.bpf.h>`
+const EXTRA_HEADERS: [(&str, &str); 1] = [("usdt.bpf.h", include_str!("../include/usdt.bpf.h"))];
+
 /// Extract vendored libbpf header files to a temporary directory.
 ///
 /// Directory and enclosed contents will be removed when return object is dropped.
@@ -51,7 +54,7 @@ fn extract_libbpf_headers_to_disk() -> Result<TempDir> {
     let tempdir = TempDir::new()?;
     let dir = tempdir.path().join("bpf");
     fs::create_dir_all(&dir)?;
-    for (filename, contents) in libbpf_sys::API_HEADERS {
+    for (filename, contents) in libbpf_sys::API_HEADERS.iter().chain(EXTRA_HEADERS.iter()) {
         let path = dir.as_path().join(filename);
         let mut file = OpenOptions::new().write(true).create(true).open(path)?;
         file.write_all(contents.as_bytes())?;
@@ -696,14 +696,7 @@ fn gen_skel_contents(_debug: bool, raw_obj_name: &str, obj_file_path: &Path) ->
         impl<'a> {name}SkelBuilder {{
             pub fn open(mut self) -> libbpf_rs::Result<Open{name}Skel<'a>> {{
                 let mut skel_config = build_skel_config()?;
-                let open_opts = self.obj_builder.opts(std::ptr::null());
-
-                let ret = unsafe {{ libbpf_sys::bpf_object__open_skeleton(skel_config.get(), &open_opts) }};
-                if ret != 0 {{
-                    return Err(libbpf_rs::Error::System(-ret));
-                }}
-
-                let obj = unsafe {{ libbpf_rs::OpenObject::from_ptr(skel_config.object_ptr())? }};
+                let obj = self.obj_builder.open_skeleton(&mut skel_config)?;
 
                 Ok(Open{name}Skel {{
                     obj,
@@ -731,12 +724,7 @@ fn gen_skel_contents(_debug: bool, raw_obj_name: &str, obj_file_path: &Path) ->
 
         impl<'a> Open{name}Skel<'a> {{
             pub fn load(mut self) -> libbpf_rs::Result<{name}Skel<'a>> {{
-                let ret = unsafe {{ libbpf_sys::bpf_object__load_skeleton(self.skel_config.get()) }};
-                if ret != 0 {{
-                    return Err(libbpf_rs::Error::System(-ret));
-                }}
-
-                let obj = unsafe {{ libbpf_rs::Object::from_ptr(self.obj.take_ptr())? }};
+                let obj = self.obj.load_skeleton(&mut self.skel_config)?;
 
                 Ok({name}Skel {{
                     obj,
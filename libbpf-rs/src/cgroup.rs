@@ -0,0 +1,222 @@
+//! Attach programs to cgroups with `BPF_PROG_ATTACH`
+//!
+//! Unlike [`Program::attach_cgroup()`], which creates a [`Link`], programs attached this way
+//! stay attached until explicitly detached or the cgroup is removed. This works on kernels
+//! without cgroup link support and allows multiple programs or overrides per attach type.
+//!
+//! For example, to list the programs attached to a cgroup for ingress traffic:
+//! ```no_run
+//! use libbpf_rs::cgroup::Cgroup;
+//! use libbpf_rs::ProgramAttachType;
+//!
+//! let cgroup = Cgroup::open("/sys/fs/cgroup/my-service").unwrap();
+//! let attached = cgroup
+//!     .query(ProgramAttachType::CgroupInetIngress, false)
+//!     .unwrap();
+//! for prog in attached.progs {
+//!     println!("{}", prog.name);
+//! }
+//! ```
+
+use std::mem;
+use std::path::Path;
+
+use bitflags::bitflags;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::Mode;
+use nix::{errno, unistd};
+
+use crate::query::ProgramInfo;
+use crate::*;
+
+bitflags! {
+    /// Flags to configure [`Cgroup::attach()`]. Without flags, a single program may be attached
+    /// per attach type and programs in descendant cgroups are not run.
+    pub struct AttachFlags: u32 {
+        const NONE           = 0;
+        /// Allow programs attached to descendant cgroups to override this one
+        const ALLOW_OVERRIDE = libbpf_sys::BPF_F_ALLOW_OVERRIDE;
+        /// Allow attaching multiple programs, which all run along with the programs of
+        /// descendant cgroups
+        const ALLOW_MULTI    = libbpf_sys::BPF_F_ALLOW_MULTI;
+    }
+}
+
+/// Programs attached to a cgroup for one [`ProgramAttachType`], as returned by
+/// [`Cgroup::query()`].
+pub struct AttachedPrograms {
+    /// Flags the programs were attached with. Always empty for effective queries.
+    pub attach_flags: AttachFlags,
+    /// Attached programs in the order they run
+    pub progs: Vec<ProgramInfo>,
+}
+
+/// A cgroup (v2) directory to attach programs to.
+pub struct Cgroup {
+    fd: i32,
+    owned: bool,
+}
+
+impl Cgroup {
+    /// Open the cgroup at `path`, e.g. `/sys/fs/cgroup/my-service`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let fd = fcntl::open(
+            path.as_ref(),
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map_err(|e| Error::System(e.as_errno().map_or(0, |e| e as i32)))
+        .context("open cgroup", path.as_ref().display())?;
+
+        Ok(Cgroup { fd, owned: true })
+    }
+
+    /// Use the cgroup referred to by `fd`, which stays owned by the caller and must outlive the
+    /// returned object.
+    pub fn from_fd(fd: i32) -> Self {
+        Cgroup { fd, owned: false }
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    /// Attach `prog` for `attach_type`.
+    ///
+    /// With [`AttachFlags::ALLOW_MULTI`], `prog` is added after the programs already attached
+    /// for `attach_type`. Otherwise it replaces the attached program, if that was attached with
+    /// the same flags.
+    pub fn attach(
+        &self,
+        prog: &Program,
+        attach_type: ProgramAttachType,
+        flags: AttachFlags,
+    ) -> Result<()> {
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_attach(prog.fd(), self.fd, attach_type as u32, flags.bits)
+        };
+        if ret != 0 {
+            return Err(Error::System(errno::errno())).context("attach cgroup", prog.name());
+        }
+
+        Ok(())
+    }
+
+    /// Atomically replace `old_prog` with `prog` for `attach_type` (`BPF_F_REPLACE`). Both
+    /// programs must be attached with [`AttachFlags::ALLOW_MULTI`].
+    pub fn replace(
+        &self,
+        prog: &Program,
+        old_prog: &Program,
+        attach_type: ProgramAttachType,
+        flags: AttachFlags,
+    ) -> Result<()> {
+        // See the comment in `gen_info_impl!` on why we need zeroed memory
+        let mut opts: libbpf_sys::bpf_prog_attach_opts = unsafe { mem::zeroed() };
+        opts.sz = mem::size_of::<libbpf_sys::bpf_prog_attach_opts>() as libbpf_sys::size_t;
+        opts.flags = flags.bits | libbpf_sys::BPF_F_REPLACE;
+        opts.replace_prog_fd = old_prog.fd();
+
+        let ret = unsafe {
+            libbpf_sys::bpf_prog_attach_xattr(prog.fd(), self.fd, attach_type as u32, &opts)
+        };
+        if ret != 0 {
+            return Err(Error::System(errno::errno())).context("replace cgroup with", prog.name());
+        }
+
+        Ok(())
+    }
+
+    /// Detach `prog` from `attach_type`. If `prog` is `None`, detach the program attached
+    /// without [`AttachFlags::ALLOW_MULTI`].
+    pub fn detach(&self, prog: Option<&Program>, attach_type: ProgramAttachType) -> Result<()> {
+        let ret = match prog {
+            Some(prog) => unsafe {
+                libbpf_sys::bpf_prog_detach2(prog.fd(), self.fd, attach_type as u32)
+            },
+            None => unsafe { libbpf_sys::bpf_prog_detach(self.fd, attach_type as u32) },
+        };
+        if ret != 0 {
+            let name = prog.map_or("program", |prog| prog.name());
+            return Err(Error::System(errno::errno())).context("detach from cgroup", name);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the programs attached for `attach_type`.
+    ///
+    /// If `effective` is set, the programs that run for this cgroup are returned instead, which
+    /// includes programs inherited from ancestor cgroups.
+    pub fn query(
+        &self,
+        attach_type: ProgramAttachType,
+        effective: bool,
+    ) -> Result<AttachedPrograms> {
+        let query_flags = if effective {
+            libbpf_sys::BPF_F_QUERY_EFFECTIVE
+        } else {
+            0
+        };
+        let attach_type = attach_type as u32;
+        let mut attach_flags = 0;
+        let mut ids: Vec<u32> = Vec::new();
+
+        // Programs may be attached between getting the count and the ids
+        loop {
+            let mut cnt = ids.len() as u32;
+            let ret = unsafe {
+                libbpf_sys::bpf_prog_query(
+                    self.fd,
+                    attach_type,
+                    query_flags,
+                    &mut attach_flags,
+                    if ids.is_empty() {
+                        std::ptr::null_mut()
+                    } else {
+                        ids.as_mut_ptr()
+                    },
+                    &mut cnt,
+                )
+            };
+            if ret != 0 {
+                let err = errno::errno();
+                if err == errno::Errno::ENOSPC as i32 {
+                    ids.resize(cnt as usize, 0);
+                    continue;
+                }
+                return Err(Error::System(err)).context("query cgroup fd", self.fd);
+            }
+
+            if cnt as usize > ids.len() {
+                ids.resize(cnt as usize, 0);
+                continue;
+            }
+            ids.truncate(cnt as usize);
+            break;
+        }
+
+        // Skip programs detached and unloaded since the query
+        let progs = ids
+            .into_iter()
+            .map(ProgramInfo::from_id)
+            .filter(
+                |info| !matches!(info, Err(e) if e.errno() == Some(errno::Errno::ENOENT as i32)),
+            )
+            .collect::<Result<Vec<_>>>()
+            .context("query cgroup fd", self.fd)?;
+
+        Ok(AttachedPrograms {
+            attach_flags: AttachFlags::from_bits_truncate(attach_flags),
+            progs,
+        })
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = unistd::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::STT_FUNC;
+use goblin::elf::Elf;
+
+use crate::*;
+
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+const LD_SO_CACHE_MAGIC_OLD: &[u8] = b"ld.so-1.7.0";
+const LD_SO_CACHE_MAGIC_NEW: &[u8] = b"glibc-ld.so.cache1.1";
+/// Size of `struct cache_file_new` in glibc's `dl-cache.h`
+const LD_SO_CACHE_HEADER_LEN: usize = 48;
+/// Size of `struct file_entry_new`
+const LD_SO_CACHE_ENTRY_LEN: usize = 24;
+const LD_SO_CACHE_FLAG_TYPE_MASK: i32 = 0x00ff;
+const LD_SO_CACHE_FLAG_ELF_LIBC6: i32 = 0x0003;
+/// Flag marking libraries of the architecture we are running on, if it has one
+#[cfg(target_arch = "x86_64")]
+const LD_SO_CACHE_FLAG_ARCH: Option<i32> = Some(0x0300);
+#[cfg(target_arch = "aarch64")]
+const LD_SO_CACHE_FLAG_ARCH: Option<i32> = Some(0x0a00);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const LD_SO_CACHE_FLAG_ARCH: Option<i32> = None;
+
+fn read_u32(data: &[u8], off: usize) -> Option<u32> {
+    data.get(off..off + 4)
+        .map(|b| u32::from_ne_bytes(b.try_into().unwrap()))
+}
+
+fn read_cstr(data: &[u8], off: usize) -> Option<&str> {
+    let bytes = data.get(off..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    std::str::from_utf8(&bytes[..len]).ok()
+}
+
+/// Look up the path of the library named `soname` in the contents of an `ld.so.cache` file.
+fn ld_so_cache_lookup(cache: &[u8], soname: &str) -> Option<PathBuf> {
+    // Caches written by older glibc versions prefix the new format with the old one. The new
+    // format header is 8 byte aligned after the old entries.
+    let mut start = 0;
+    if cache.starts_with(LD_SO_CACHE_MAGIC_OLD) {
+        let nlibs = read_u32(cache, 12)? as usize;
+        start = (16 + nlibs * 12 + 7) & !7;
+    }
+
+    let cache = cache.get(start..)?;
+    if !cache.starts_with(LD_SO_CACHE_MAGIC_NEW) {
+        return None;
+    }
+
+    // String offsets are relative to the start of the new format header
+    let nlibs = read_u32(cache, LD_SO_CACHE_MAGIC_NEW.len())? as usize;
+    (0..nlibs).find_map(|i| {
+        let entry = LD_SO_CACHE_HEADER_LEN + i * LD_SO_CACHE_ENTRY_LEN;
+        let flags = read_u32(cache, entry)? as i32;
+        if flags & LD_SO_CACHE_FLAG_TYPE_MASK != LD_SO_CACHE_FLAG_ELF_LIBC6 {
+            return None;
+        }
+        if let Some(arch) = LD_SO_CACHE_FLAG_ARCH {
+            if flags & !LD_SO_CACHE_FLAG_TYPE_MASK != arch {
+                return None;
+            }
+        }
+
+        let key = read_cstr(cache, read_u32(cache, entry + 4)? as usize)?;
+        if key != soname {
+            return None;
+        }
+
+        read_cstr(cache, read_u32(cache, entry + 8)? as usize).map(PathBuf::from)
+    })
+}
+
+/// Resolve the path of the binary or shared library `binary`.
+///
+/// Paths are returned as is. Bare library names such as `libc.so.6` are looked up in
+/// `LD_LIBRARY_PATH` and then in `/etc/ld.so.cache`, like the dynamic loader does.
+pub(crate) fn resolve_binary_path(binary: &Path) -> Result<PathBuf> {
+    let name = match binary.to_str() {
+        Some(name) if !name.contains('/') && name.contains(".so") => name,
+        _ => return Ok(binary.to_path_buf()),
+    };
+
+    if let Some(paths) = env::var_os("LD_LIBRARY_PATH") {
+        if let Some(path) = env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+        {
+            return Ok(path);
+        }
+    }
+
+    fs::read(LD_SO_CACHE)
+        .ok()
+        .and_then(|cache| ld_so_cache_lookup(&cache, name))
+        .ok_or_else(|| {
+            Error::InvalidInput(format!(
+                "Library {} not found in LD_LIBRARY_PATH or {}",
+                name, LD_SO_CACHE
+            ))
+        })
+}
+
+/// Translate the virtual address `addr` to a file offset using the loadable segment containing
+/// it. This accounts for PIE and non-PIE binaries alike.
+pub(crate) fn vaddr_to_offset(elf: &Elf, addr: u64) -> Option<u64> {
+    elf.program_headers
+        .iter()
+        .find(|phdr| {
+            phdr.p_type == PT_LOAD && phdr.p_vaddr <= addr && addr < phdr.p_vaddr + phdr.p_memsz
+        })
+        .map(|phdr| addr - phdr.p_vaddr + phdr.p_offset)
+}
+
+/// Returns the file offset of the function `symbol` in the ELF file at `path`, suitable for
+/// attaching a uprobe.
+///
+/// Both `.symtab` and `.dynsym` are searched. It is an error if several functions with this name
+/// exist at different addresses.
+pub(crate) fn find_symbol_offset(path: &Path, symbol: &str) -> Result<usize> {
+    let data = fs::read(path)
+        .map_err(|e| Error::InvalidInput(format!("Failed to read {}: {}", path.display(), e)))?;
+    let elf = Elf::parse(&data).map_err(|e| {
+        Error::InvalidInput(format!("Failed to parse ELF {}: {}", path.display(), e))
+    })?;
+
+    let mut addrs: Vec<u64> = elf
+        .syms
+        .iter()
+        .map(|sym| (sym, elf.strtab.get(sym.st_name)))
+        .chain(
+            elf.dynsyms
+                .iter()
+                .map(|sym| (sym, elf.dynstrtab.get(sym.st_name))),
+        )
+        .filter(|(sym, name)| {
+            sym.st_type() == STT_FUNC
+                && sym.st_shndx != SHN_UNDEF as usize
+                && sym.st_value != 0
+                && matches!(name, Some(Ok(name)) if *name == symbol)
+        })
+        .map(|(sym, _)| sym.st_value)
+        .collect();
+    addrs.sort_unstable();
+    addrs.dedup();
+
+    let addr = match addrs[..] {
+        [addr] => addr,
+        [] if elf.syms.is_empty() => {
+            return Err(Error::InvalidInput(format!(
+                "Function {} not found in {}, which has no .symtab (stripped binary?)",
+                symbol,
+                path.display()
+            )))
+        }
+        [] => {
+            return Err(Error::InvalidInput(format!(
+                "Function {} not found in {}",
+                symbol,
+                path.display()
+            )))
+        }
+        _ => {
+            return Err(Error::InvalidInput(format!(
+                "Function {} is ambiguous in {}: found at addresses {:x?}",
+                symbol,
+                path.display(),
+                addrs
+            )))
+        }
+    };
+
+    vaddr_to_offset(&elf, addr)
+        .map(|off| off as usize)
+        .ok_or_else(|| {
+            Error::InvalidInput(format!(
+                "Address {:#x} of function {} is not in a loadable segment of {}",
+                addr,
+                symbol,
+                path.display()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ld_so_cache_entry(flags: i32, key: u32, value: u32) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&flags.to_ne_bytes());
+        entry.extend_from_slice(&key.to_ne_bytes());
+        entry.extend_from_slice(&value.to_ne_bytes());
+        entry.extend_from_slice(&[0; 12]);
+        entry
+    }
+
+    #[test]
+    fn test_ld_so_cache_lookup() {
+        let flags = LD_SO_CACHE_FLAG_ELF_LIBC6 | LD_SO_CACHE_FLAG_ARCH.unwrap_or(0);
+        let strings = b"libfoo.so.1\0/usr/lib/libfoo.so.1\0libbar.so\0/lib/libbar.so\0";
+        let str_off = (LD_SO_CACHE_HEADER_LEN + 2 * LD_SO_CACHE_ENTRY_LEN) as u32;
+
+        let mut cache = LD_SO_CACHE_MAGIC_NEW.to_vec();
+        cache.extend_from_slice(&2u32.to_ne_bytes());
+        cache.resize(LD_SO_CACHE_HEADER_LEN, 0);
+        cache.extend(ld_so_cache_entry(flags, str_off, str_off + 12));
+        cache.extend(ld_so_cache_entry(flags, str_off + 33, str_off + 43));
+        cache.extend_from_slice(strings);
+
+        assert_eq!(
+            ld_so_cache_lookup(&cache, "libfoo.so.1"),
+            Some(PathBuf::from("/usr/lib/libfoo.so.1"))
+        );
+        assert_eq!(
+            ld_so_cache_lookup(&cache, "libbar.so"),
+            Some(PathBuf::from("/lib/libbar.so"))
+        );
+        assert_eq!(ld_so_cache_lookup(&cache, "libbaz.so"), None);
+        assert_eq!(ld_so_cache_lookup(b"garbage", "libfoo.so.1"), None);
+    }
+
+    #[no_mangle]
+    #[inline(never)]
+    extern "C" fn libbpf_rs_elf_test_func() {}
+
+    #[test]
+    fn test_find_symbol_offset() {
+        libbpf_rs_elf_test_func();
+
+        let exe = Path::new("/proc/self/exe");
+        assert!(find_symbol_offset(exe, "libbpf_rs_elf_test_func").is_ok());
+        assert!(find_symbol_offset(exe, "libbpf_rs_no_such_func").is_err());
+    }
+}
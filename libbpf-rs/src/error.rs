@@ -1,16 +1,101 @@
+use std::fmt::Display;
+use std::io;
 use std::result;
 
+use nix::errno::Errno;
 use thiserror::Error;
 
 /// Canonical error type for this crate.
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("System error, errno: {0}")]
+    #[error("System error, errno: {0} ({})", Errno::from_i32(*.0).desc())]
     System(i32),
     #[error("Input input: {0}")]
     InvalidInput(String),
     #[error("Internal error: {0}")]
     Internal(String),
+    /// The kernel rejected program `name` on load, with the verifier output in `log`.
+    #[error("Program {name} rejected: {log}")]
+    ProgramRejected { name: String, log: String },
+    /// Operation `op` (e.g. "attach kprobe" or "update") on the program, map or path `name`
+    /// failed because of `source`.
+    ///
+    /// Errno values from failing kernel and libbpf calls are returned wrapped in this variant
+    /// rather than as a bare [`Error::System`]. Use [`Error::errno()`] or [`Error::kind()`] to
+    /// match on them.
+    #[error("Failed to {op} {name}: {source}")]
+    Operation {
+        op: &'static str,
+        name: String,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The errno that caused this error, if any.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            Error::System(errno) => Some(*errno),
+            Error::Operation { source, .. } => source.errno(),
+            _ => None,
+        }
+    }
+
+    /// The [`io::ErrorKind`] matching this error, e.g. [`io::ErrorKind::NotFound`] for
+    /// `ENOENT`.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::System(errno) => io::Error::from_raw_os_error(*errno).kind(),
+            Error::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            Error::Operation { source, .. } => source.kind(),
+            _ => io::ErrorKind::Other,
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::new(e.kind(), e)
+    }
 }
 
 pub type Result<T> = result::Result<T, Error>;
+
+pub(crate) trait ErrorExt {
+    /// Wrap the error in [`Error::Operation`] for operation `op` on `name`.
+    fn context<D: Display>(self, op: &'static str, name: D) -> Self;
+}
+
+impl<T> ErrorExt for Result<T> {
+    fn context<D: Display>(self, op: &'static str, name: D) -> Self {
+        self.map_err(|e| Error::Operation {
+            op,
+            name: name.to_string(),
+            source: Box::new(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let err = Err::<(), _>(Error::System(Errno::ENOENT as i32))
+            .context("pin", "/sys/fs/bpf/map")
+            .unwrap_err();
+
+        assert_eq!(err.errno(), Some(Errno::ENOENT as i32));
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "Failed to pin /sys/fs/bpf/map: System error, errno: 2 (No such file or directory)"
+        );
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::NotFound);
+
+        let err = Error::InvalidInput("bad".to_string());
+        assert_eq!(err.errno(), None);
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
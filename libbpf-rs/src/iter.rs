@@ -18,7 +18,7 @@ impl Iter {
         let link_fd = link.get_fd();
         let fd = unsafe { libbpf_sys::bpf_iter_create(link_fd) };
         if fd < 0 {
-            return Err(Error::System(errno::errno()));
+            return Err(Error::System(errno::errno())).context("create iter from link fd", link_fd);
         }
         Ok(Self { fd })
     }
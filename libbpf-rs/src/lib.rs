@@ -66,27 +66,49 @@
 //!
 //! [See example here](https://github.com/libbpf/libbpf-rs/tree/master/examples/runqslower).
 
+pub mod cgroup;
+mod elf;
 mod error;
 mod iter;
 mod link;
 mod map;
+mod mmap;
 mod object;
 mod perf_buffer;
+mod perf_event;
+mod print;
+pub mod probe;
 mod program;
 pub mod query;
 mod ringbuf;
 /// Used for skeleton -- an end user may not consider this API stable
 #[doc(hidden)]
 pub mod skeleton;
+mod tc;
+mod typed_map;
+mod usdt;
 mod util;
+mod xdp;
 
 pub use libbpf_sys;
 
+pub(crate) use crate::error::ErrorExt;
 pub use crate::error::{Error, Result};
 pub use crate::iter::Iter;
 pub use crate::link::Link;
-pub use crate::map::{Map, MapFlags, MapType, OpenMap};
-pub use crate::object::{Object, ObjectBuilder, OpenObject};
+pub use crate::map::{BatchedMapIter, Map, MapFlags, MapIter, MapType, OpenMap};
+pub use crate::mmap::{MmapArray, MmapArrayMut};
+pub use crate::object::{KconfigValue, Object, ObjectBuilder, OpenObject};
 pub use crate::perf_buffer::{PerfBuffer, PerfBufferBuilder};
-pub use crate::program::{OpenProgram, Program, ProgramAttachType, ProgramType};
+#[cfg(feature = "log")]
+pub use crate::print::print_to_log;
+pub use crate::print::{set_print, PrintCallback, PrintLevel};
+pub use crate::program::{
+    KprobeOpts, KprobesOpts, OpenProgram, Program, ProgramAttachType, ProgramType, TestRunResult,
+    UprobeOpts,
+};
 pub use crate::ringbuf::{RingBuffer, RingBufferBuilder};
+pub use crate::tc::{TcAttachPoint, TcFilter, TcHook, TcOpts};
+pub use crate::typed_map::{Pod, TypedMap};
+pub use crate::util::num_possible_cpus;
+pub use crate::xdp::{Xdp, XdpFlags, XdpInfo};
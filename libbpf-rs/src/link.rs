@@ -1,5 +1,8 @@
-use nix::errno;
-use std::path::Path;
+use nix::{errno, libc, unistd};
+use std::fs;
+use std::mem::{self, size_of};
+use std::path::{Path, PathBuf};
+use std::ptr;
 
 use crate::*;
 
@@ -8,12 +11,99 @@ use crate::*;
 /// This struct is used to model ownership. The underlying program will be detached
 /// when this object is dropped if nothing else is holding a reference count.
 pub struct Link {
+    /// Null for links created without libbpf, which own their file descriptor
     ptr: *mut libbpf_sys::bpf_link,
+    fd: i32,
+    /// Only tracked for links not managed by libbpf
+    pin_path: Option<PathBuf>,
+    disconnected: bool,
+    /// Links making up an aggregate link, e.g. the uprobes of a USDT probe, which has no file
+    /// descriptor of its own
+    links: Option<Vec<Link>>,
+    /// Spec ids of a USDT link, released when dropped
+    usdt_specs: Option<usdt::SpecIds>,
+}
+
+/// `BPF_PERF_EVENT` from `enum bpf_attach_type`, newer than the bundled uapi headers.
+const BPF_PERF_EVENT: u32 = 41;
+
+/// `link_create` member of `union bpf_attr` for `BPF_PERF_EVENT` links.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventLinkCreateAttr {
+    prog_fd: u32,
+    target_fd: u32,
+    attach_type: u32,
+    flags: u32,
+    bpf_cookie: u64,
 }
 
 impl Link {
     pub(crate) fn new(ptr: *mut libbpf_sys::bpf_link) -> Self {
-        Link { ptr }
+        Link {
+            ptr,
+            fd: unsafe { libbpf_sys::bpf_link__fd(ptr) },
+            pin_path: None,
+            disconnected: false,
+            links: None,
+            usdt_specs: None,
+        }
+    }
+
+    /// Attach `prog_fd` to the perf event `pfd` with a BPF cookie, which libbpf does not support.
+    ///
+    /// The link holds its own reference to the perf event, so `pfd` is always closed.
+    pub(crate) fn new_perf_event(prog_fd: i32, pfd: i32, cookie: u64) -> Result<Self> {
+        let attr = PerfEventLinkCreateAttr {
+            prog_fd: prog_fd as u32,
+            target_fd: pfd as u32,
+            attach_type: BPF_PERF_EVENT,
+            bpf_cookie: cookie,
+            ..Default::default()
+        };
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                libbpf_sys::BPF_LINK_CREATE,
+                &attr as *const PerfEventLinkCreateAttr,
+                size_of::<PerfEventLinkCreateAttr>() as u32,
+            )
+        };
+        let err = errno::errno();
+        let _ = unistd::close(pfd);
+        if fd < 0 {
+            return Err(Error::System(err));
+        }
+
+        Ok(Link {
+            ptr: ptr::null_mut(),
+            fd: fd as i32,
+            pin_path: None,
+            disconnected: false,
+            links: None,
+            usdt_specs: None,
+        })
+    }
+
+    /// Combine `links` into one link, which detaches all of them when dropped.
+    pub(crate) fn new_multi(links: Vec<Link>) -> Self {
+        Link {
+            ptr: ptr::null_mut(),
+            fd: -1,
+            pin_path: None,
+            disconnected: false,
+            links: Some(links),
+            usdt_specs: None,
+        }
+    }
+
+    /// Combine the links of all locations of a USDT probe into one link, which also releases the
+    /// probe's spec ids when dropped.
+    pub(crate) fn new_usdt(links: Vec<Link>, spec_ids: usdt::SpecIds) -> Self {
+        let mut link = Self::new_multi(links);
+        link.usdt_specs = Some(spec_ids);
+        link
     }
 
     /// Takes ownership from pointer.
@@ -27,9 +117,9 @@ impl Link {
 
     /// Replace the underlying prog with `prog`.
     pub fn update_prog(&mut self, prog: Program) -> Result<()> {
-        let ret = unsafe { libbpf_sys::bpf_link__update_program(self.ptr, prog.ptr) };
+        let ret = unsafe { libbpf_sys::bpf_link_update(self.fd, prog.fd(), ptr::null()) };
         if ret != 0 {
-            Err(Error::System(errno::errno()))
+            Err(Error::System(errno::errno())).context("update link with", prog.name())
         } else {
             Ok(())
         }
@@ -45,19 +135,43 @@ impl Link {
     /// exit of userspace program doesn't trigger automatic detachment and clean up
     /// inside the kernel.
     pub fn disconnect(&mut self) {
-        unsafe { libbpf_sys::bpf_link__disconnect(self.ptr) }
+        if let Some(links) = &mut self.links {
+            links.iter_mut().for_each(Link::disconnect);
+            self.disconnected = true;
+        } else if self.ptr.is_null() {
+            self.disconnected = true;
+        } else {
+            unsafe { libbpf_sys::bpf_link__disconnect(self.ptr) }
+        }
     }
 
     /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// this link to bpffs.
     pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        let path_c = util::path_to_cstring(&path)?;
         let path_ptr = path_c.as_ptr();
 
+        if self.links.is_some() {
+            return Err(Error::InvalidInput(
+                "Aggregate links can't be pinned".to_string(),
+            ));
+        }
+
+        if self.ptr.is_null() {
+            let ret = unsafe { libbpf_sys::bpf_obj_pin(self.fd, path_ptr) };
+            if ret != 0 {
+                return Err(Error::System(errno::errno()))
+                    .context("pin link to", path.as_ref().display());
+            }
+
+            self.pin_path = Some(path.as_ref().to_path_buf());
+            return Ok(());
+        }
+
         let ret = unsafe { libbpf_sys::bpf_link__pin(self.ptr, path_ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("pin link to", path.as_ref().display())
         } else {
             Ok(())
         }
@@ -66,23 +180,52 @@ impl Link {
     /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// from bpffs
     pub fn unpin(&mut self) -> Result<()> {
+        if self.ptr.is_null() {
+            if let Some(path) = self.pin_path.take() {
+                fs::remove_file(&path)
+                    .map_err(|e| Error::System(e.raw_os_error().unwrap_or(0)))
+                    .context("unpin link from", path.display())?;
+            }
+
+            return Ok(());
+        }
+
         let ret = unsafe { libbpf_sys::bpf_link__unpin(self.ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("unpin link fd", self.fd)
         } else {
             Ok(())
         }
     }
 
     /// Returns the file descriptor of the link.
+    ///
+    /// Aggregate links, like those of USDT probes or [`Program::attach_kprobes()`], consist of
+    /// several links and have no file descriptor, `-1` is returned.
     pub fn get_fd(&self) -> i32 {
-        unsafe { libbpf_sys::bpf_link__fd(self.ptr) }
+        self.fd
     }
 }
 
 impl Drop for Link {
     fn drop(&mut self) {
+        if self.disconnected {
+            // The probes of a disconnected USDT link still use its specs, keep them reserved
+            if let Some(spec_ids) = self.usdt_specs.take() {
+                mem::forget(spec_ids);
+            }
+        }
+
+        if self.ptr.is_null() {
+            // Like libbpf, keep the file descriptor of disconnected links open so the
+            // attachment outlives this object
+            if !self.disconnected && self.fd >= 0 {
+                let _ = unistd::close(self.fd);
+            }
+            return;
+        }
+
         let _ = unsafe { libbpf_sys::bpf_link__destroy(self.ptr) };
     }
 }
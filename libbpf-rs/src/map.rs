@@ -1,5 +1,10 @@
 use core::ffi::c_void;
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fs;
+use std::mem;
 use std::path::Path;
 use std::ptr;
 
@@ -8,6 +13,7 @@ use nix::{errno, unistd};
 use num_enum::TryFromPrimitive;
 use strum_macros::Display;
 
+use crate::query::{self, MapInfo};
 use crate::*;
 
 /// Represents a parsed but not yet loaded BPF map.
@@ -25,6 +31,96 @@ impl OpenMap {
         OpenMap { ptr }
     }
 
+    pub fn name(&self) -> Result<&str> {
+        let name_ptr = unsafe { libbpf_sys::bpf_map__name(self.ptr) };
+        if name_ptr.is_null() {
+            return Err(Error::Internal("Null string".to_owned()));
+        }
+
+        unsafe { CStr::from_ptr(name_ptr) }
+            .to_str()
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    pub fn map_type(&self) -> MapType {
+        match MapType::try_from(unsafe { libbpf_sys::bpf_map__type(self.ptr) }) {
+            Ok(t) => t,
+            Err(_) => MapType::Unknown,
+        }
+    }
+
+    /// Key size in bytes
+    pub fn key_size(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__key_size(self.ptr) }
+    }
+
+    /// Value size in bytes
+    pub fn value_size(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__value_size(self.ptr) }
+    }
+
+    pub fn max_entries(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__max_entries(self.ptr) }
+    }
+
+    /// Map creation flags
+    pub fn map_flags(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__map_flags(self.ptr) }
+    }
+
+    pub fn numa_node(&self) -> u32 {
+        unsafe { libbpf_sys::bpf_map__numa_node(self.ptr) }
+    }
+
+    pub fn set_map_type(&mut self, map_type: MapType) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_type(self.ptr, map_type as u32) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("set map type of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
+    /// Set the key size in bytes.
+    pub fn set_key_size(&mut self, size: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_key_size(self.ptr, size) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("set key size of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
+    /// Set the value size in bytes.
+    pub fn set_value_size(&mut self, size: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_value_size(self.ptr, size) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret))
+                .context("set value size of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
+    /// Set the NUMA node the map is allocated on. Only takes effect together with the
+    /// `BPF_F_NUMA_NODE` map flag.
+    pub fn set_numa_node(&mut self, numa_node: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_numa_node(self.ptr, numa_node) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("set numa node of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
     pub fn set_map_ifindex(&mut self, idx: u32) {
         unsafe { libbpf_sys::bpf_map__set_ifindex(self.ptr, idx) };
     }
@@ -40,7 +136,8 @@ impl OpenMap {
 
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            return Err(Error::System(-ret));
+            return Err(Error::System(-ret))
+                .context("set initial value of", self.name().unwrap_or(""));
         }
 
         Ok(())
@@ -51,7 +148,20 @@ impl OpenMap {
 
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            return Err(Error::System(-ret));
+            return Err(Error::System(-ret))
+                .context("set max entries of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
+    /// Set the map creation flags, e.g. `BPF_F_MMAPABLE` or `BPF_F_NO_PREALLOC`.
+    pub fn set_map_flags(&mut self, flags: u32) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_map__set_map_flags(self.ptr, flags) };
+
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("set map flags of", self.name().unwrap_or(""));
         }
 
         Ok(())
@@ -63,11 +173,12 @@ impl OpenMap {
 
     /// Reuse an already-pinned map for `self`.
     pub fn reuse_pinned_map<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let cstring = util::path_to_cstring(path)?;
+        let cstring = util::path_to_cstring(&path)?;
 
         let fd = unsafe { libbpf_sys::bpf_obj_get(cstring.as_ptr()) };
         if fd < 0 {
-            return Err(Error::System(errno::errno()));
+            return Err(Error::System(errno::errno()))
+                .context("reuse pinned map", path.as_ref().display());
         }
 
         let ret = unsafe { libbpf_sys::bpf_map__reuse_fd(self.ptr, fd) };
@@ -78,7 +189,7 @@ impl OpenMap {
         let _ = unistd::close(fd);
 
         if ret != 0 {
-            return Err(Error::System(-ret));
+            return Err(Error::System(-ret)).context("reuse pinned map", path.as_ref().display());
         }
 
         Ok(())
@@ -87,15 +198,21 @@ impl OpenMap {
 
 /// Represents a created map.
 ///
+/// A `Map` is either part of an [`Object`] or a standalone map that owns its file descriptor,
+/// e.g. one created with [`Map::create()`].
+///
 /// Some methods require working with raw bytes. You may find libraries such as
-/// [`plain`](https://crates.io/crates/plain) helpful.
+/// [`plain`](https://crates.io/crates/plain) helpful, or wrap the map in a [`TypedMap`].
 pub struct Map {
     fd: i32,
     name: String,
     ty: libbpf_sys::bpf_map_type,
     key_size: u32,
     value_size: u32,
+    /// Null for standalone maps, which are not managed by libbpf
     ptr: *mut libbpf_sys::bpf_map,
+    /// Cached result of `Map::has_spin_lock()`
+    spin_lock: Cell<Option<bool>>,
 }
 
 impl Map {
@@ -114,7 +231,124 @@ impl Map {
             key_size,
             value_size,
             ptr,
+            spin_lock: Cell::new(None),
+        }
+    }
+
+    /// Create a new standalone map that is not part of any [`Object`].
+    ///
+    /// The map is destroyed when the returned `Map` is dropped, unless it is pinned or otherwise
+    /// referenced (e.g. as inner map of a map-in-map). `flags` are map creation flags such as
+    /// `BPF_F_NO_PREALLOC` or `BPF_F_MMAPABLE`.
+    ///
+    /// A standalone map can serve as inner map template for [`OpenMap::set_inner_map_fd()`].
+    pub fn create(
+        map_type: MapType,
+        key_size: u32,
+        value_size: u32,
+        max_entries: u32,
+        flags: u32,
+    ) -> Result<Self> {
+        let fd = unsafe {
+            libbpf_sys::bpf_create_map(
+                map_type.clone() as u32,
+                key_size as i32,
+                value_size as i32,
+                max_entries as i32,
+                flags,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::System(errno::errno())).context("create map of type", map_type);
         }
+
+        Self::from_fd(fd)
+    }
+
+    /// Create a new standalone [`MapType::ArrayOfMaps`] or [`MapType::HashOfMaps`].
+    ///
+    /// `inner` is used as template for the maps that can be stored in the created map. See
+    /// [`Map::create()`] for details.
+    pub fn create_map_in_map(
+        map_type: MapType,
+        key_size: u32,
+        inner: &Map,
+        max_entries: u32,
+        flags: u32,
+    ) -> Result<Self> {
+        if map_type != MapType::ArrayOfMaps && map_type != MapType::HashOfMaps {
+            return Err(Error::InvalidInput(format!(
+                "Must use an ArrayOfMaps or HashOfMaps map type, not {}",
+                map_type
+            )));
+        }
+
+        let fd = unsafe {
+            libbpf_sys::bpf_create_map_in_map(
+                map_type.clone() as u32,
+                ptr::null(),
+                key_size as i32,
+                inner.fd(),
+                max_entries as i32,
+                flags,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::System(errno::errno())).context("create map of type", map_type);
+        }
+
+        Self::from_fd(fd)
+    }
+
+    /// Get an owned handle to the map with the given kernel map id.
+    pub fn from_id(id: u32) -> Result<Self> {
+        let fd = unsafe { libbpf_sys::bpf_map_get_fd_by_id(id) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno())).context("open map with id", id);
+        }
+
+        Self::from_fd(fd)
+    }
+
+    /// Get an owned handle to the map pinned at `path` in bpffs.
+    ///
+    /// Unlike [`OpenMap::reuse_pinned_map()`], this does not require an [`Object`] and can be
+    /// used to access maps pinned by other processes.
+    pub fn from_pinned_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path_ptr) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()))
+                .context("open pinned map", path.as_ref().display());
+        }
+
+        Self::from_fd(fd)
+    }
+
+    /// Build a standalone `Map` from a map file descriptor.
+    ///
+    /// Type, key and value size and name of the map are queried from the kernel. The returned
+    /// `Map` takes ownership of `fd` and closes it when dropped. `fd` is also closed if an error
+    /// is returned.
+    pub fn from_fd(fd: i32) -> Result<Self> {
+        let info = match query::get_info_by_fd::<libbpf_sys::bpf_map_info>(fd) {
+            Ok(info) => info,
+            Err(e) => {
+                let _ = unistd::close(fd);
+                return Err(e);
+            }
+        };
+
+        Ok(Map::new(
+            fd,
+            query::name_arr_to_string(&info.name, ""),
+            info.type_,
+            info.key_size,
+            info.value_size,
+            ptr::null_mut(),
+        ))
     }
 
     pub fn name(&self) -> &str {
@@ -143,16 +377,69 @@ impl Map {
         self.value_size
     }
 
+    /// Returns whether the map's value type contains a `struct bpf_spin_lock`.
+    ///
+    /// Only such maps can be accessed with [`MapFlags::LOCK`]. The value type is determined from
+    /// the map's BTF, so maps without BTF never contain a spin lock.
+    pub fn has_spin_lock(&self) -> Result<bool> {
+        if let Some(has_lock) = self.spin_lock.get() {
+            return Ok(has_lock);
+        }
+
+        let info = MapInfo::from_fd(self.fd)?;
+        let value_type_id = if self.ptr.is_null() {
+            info.btf_value_type_id
+        } else {
+            unsafe { libbpf_sys::bpf_map__btf_value_type_id(self.ptr) }
+        };
+        if info.btf_id == 0 || value_type_id == 0 {
+            self.spin_lock.set(Some(false));
+            return Ok(false);
+        }
+
+        let mut btf = ptr::null_mut();
+        let ret = unsafe { libbpf_sys::btf__get_from_id(info.btf_id, &mut btf) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("load BTF of", &self.name);
+        }
+
+        let has_lock = btf_struct_has_spin_lock(btf, value_type_id);
+        unsafe { libbpf_sys::btf__free(btf) };
+
+        self.spin_lock.set(Some(has_lock));
+        Ok(has_lock)
+    }
+
+    /// Rejects [`MapFlags::LOCK`] for maps whose value does not contain a spin lock.
+    fn check_lock_flag(&self, flags: MapFlags) -> Result<()> {
+        if flags.contains(MapFlags::LOCK) && !self.has_spin_lock()? {
+            return Err(Error::InvalidInput(format!(
+                "MapFlags::LOCK requires the value of map {} to contain a bpf_spin_lock",
+                self.name()
+            )));
+        }
+
+        Ok(())
+    }
+
     /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// this map to bpffs.
     pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        let path_c = util::path_to_cstring(&path)?;
         let path_ptr = path_c.as_ptr();
 
-        let ret = unsafe { libbpf_sys::bpf_map__pin(self.ptr, path_ptr) };
+        let ret = if self.ptr.is_null() {
+            match unsafe { libbpf_sys::bpf_obj_pin(self.fd, path_ptr) } {
+                0 => 0,
+                _ => -errno::errno(),
+            }
+        } else {
+            unsafe { libbpf_sys::bpf_map__pin(self.ptr, path_ptr) }
+        };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("pin", path.as_ref().display())
         } else {
             Ok(())
         }
@@ -161,13 +448,19 @@ impl Map {
     /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// from bpffs
     pub fn unpin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        if self.ptr.is_null() {
+            return fs::remove_file(&path)
+                .map_err(|e| Error::System(e.raw_os_error().unwrap_or(0)))
+                .context("unpin", path.as_ref().display());
+        }
+
+        let path_c = util::path_to_cstring(&path)?;
         let path_ptr = path_c.as_ptr();
 
         let ret = unsafe { libbpf_sys::bpf_map__unpin(self.ptr, path_ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("unpin", path.as_ref().display())
         } else {
             Ok(())
         }
@@ -176,7 +469,17 @@ impl Map {
     /// Returns map value as `Vec` of `u8`.
     ///
     /// `key` must have exactly [`Map::key_size()`] elements.
+    ///
+    /// If the map is one of the per-cpu data structures, [`Map::lookup_percpu()`] must be used
+    /// instead.
     pub fn lookup(&self, key: &[u8], flags: MapFlags) -> Result<Option<Vec<u8>>> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "lookup_percpu() must be used for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
         if key.len() != self.key_size() as usize {
             return Err(Error::InvalidInput(format!(
                 "key_size {} != {}",
@@ -185,6 +488,8 @@ impl Map {
             )));
         };
 
+        self.check_lock_flag(flags)?;
+
         let mut out: Vec<u8> = Vec::with_capacity(self.value_size() as usize);
 
         let ret = unsafe {
@@ -206,7 +511,7 @@ impl Map {
             if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
                 Ok(None)
             } else {
-                Err(Error::System(errno))
+                Err(Error::System(errno)).context("lookup", &self.name)
             }
         }
     }
@@ -230,17 +535,24 @@ impl Map {
         if ret == 0 {
             Ok(())
         } else {
-            Err(Error::System(errno::errno()))
+            Err(Error::System(errno::errno())).context("delete from", &self.name)
         }
     }
 
     /// Same as [`Map::lookup()`] except this also deletes the key from the map.
     ///
     /// Note that this operation is currently only implemented in the kernel for [`MapType::Queue`]
-    /// and [`MapType::Stack`].
+    /// and [`MapType::Stack`], which do not have keys. Use [`Map::pop()`] for those.
     ///
     /// `key` must have exactly [`Map::key_size()`] elements.
     pub fn lookup_and_delete(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "lookup_and_delete() is not supported for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
         if key.len() != self.key_size() as usize {
             return Err(Error::InvalidInput(format!(
                 "key_size {} != {}",
@@ -269,7 +581,7 @@ impl Map {
             if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
                 Ok(None)
             } else {
-                Err(Error::System(errno))
+                Err(Error::System(errno)).context("lookup and delete from", &self.name)
             }
         }
     }
@@ -278,7 +590,17 @@ impl Map {
     ///
     /// `key` must have exactly [`Map::key_size()`] elements. `value` must have exatly
     /// [`Map::value_size()`] elements.
+    ///
+    /// If the map is one of the per-cpu data structures, [`Map::update_percpu()`] must be used
+    /// instead.
     pub fn update(&mut self, key: &[u8], value: &[u8], flags: MapFlags) -> Result<()> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "update_percpu() must be used for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
         if key.len() != self.key_size() as usize {
             return Err(Error::InvalidInput(format!(
                 "key_size {} != {}",
@@ -295,6 +617,8 @@ impl Map {
             )));
         };
 
+        self.check_lock_flag(flags)?;
+
         let ret = unsafe {
             libbpf_sys::bpf_map_update_elem(
                 self.fd as i32,
@@ -307,10 +631,450 @@ impl Map {
         if ret == 0 {
             Ok(())
         } else {
-            Err(Error::System(errno::errno()))
+            Err(Error::System(errno::errno())).context("update", &self.name)
+        }
+    }
+
+    /// Push `value` onto a [`MapType::Queue`] or [`MapType::Stack`].
+    ///
+    /// `value` must have exactly [`Map::value_size()`] elements. Pass [`MapFlags::EXIST`] to
+    /// replace the oldest element if the map is full.
+    pub fn push(&mut self, value: &[u8], flags: MapFlags) -> Result<()> {
+        self.check_keyless()?;
+
+        if value.len() != self.value_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "value_size {} != {}",
+                value.len(),
+                self.value_size()
+            )));
+        };
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_elem(
+                self.fd,
+                ptr::null(),
+                value.as_ptr() as *const c_void,
+                flags.bits,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno())).context("push to", &self.name)
+        }
+    }
+
+    /// Remove and return the next element of a [`MapType::Queue`] or [`MapType::Stack`].
+    ///
+    /// Returns `None` if the map is empty.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>> {
+        self.check_keyless()?;
+
+        let mut out: Vec<u8> = Vec::with_capacity(self.value_size() as usize);
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_lookup_and_delete_elem(
+                self.fd,
+                ptr::null(),
+                out.as_mut_ptr() as *mut c_void,
+            )
+        };
+
+        self.keyless_result(ret, out)
+            .context("pop from", &self.name)
+    }
+
+    /// Return the next element of a [`MapType::Queue`] or [`MapType::Stack`] without removing
+    /// it.
+    ///
+    /// Returns `None` if the map is empty.
+    pub fn peek(&self) -> Result<Option<Vec<u8>>> {
+        self.check_keyless()?;
+
+        let mut out: Vec<u8> = Vec::with_capacity(self.value_size() as usize);
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_lookup_elem(self.fd, ptr::null(), out.as_mut_ptr() as *mut c_void)
+        };
+
+        self.keyless_result(ret, out).context("peek", &self.name)
+    }
+
+    fn check_keyless(&self) -> Result<()> {
+        match self.map_type() {
+            MapType::Queue | MapType::Stack => Ok(()),
+            ty => Err(Error::InvalidInput(format!(
+                "operation is only supported for Queue and Stack maps (type of the map is {})",
+                ty
+            ))),
+        }
+    }
+
+    fn keyless_result(&self, ret: i32, mut out: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if ret == 0 {
+            unsafe {
+                out.set_len(self.value_size() as usize);
+            }
+            Ok(Some(out))
+        } else {
+            let errno = errno::errno();
+            if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                Ok(None)
+            } else {
+                Err(Error::System(errno))
+            }
+        }
+    }
+
+    /// Store `inner` at `key` in a [`MapType::ArrayOfMaps`] or [`MapType::HashOfMaps`].
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements. `inner` must be compatible with the
+    /// inner map template of this map.
+    pub fn update_inner_map(&mut self, key: &[u8], inner: &Map, flags: MapFlags) -> Result<()> {
+        self.check_map_in_map()?;
+
+        self.update(key, &(inner.fd() as u32).to_ne_bytes(), flags)
+    }
+
+    /// Returns the id of the inner map stored at `key` in a [`MapType::ArrayOfMaps`] or
+    /// [`MapType::HashOfMaps`].
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements.
+    pub fn lookup_inner_map_id(&self, key: &[u8]) -> Result<Option<u32>> {
+        self.check_map_in_map()?;
+
+        Ok(self.lookup(key, MapFlags::ANY)?.map(|id| {
+            let mut buf = [0; 4];
+            buf.copy_from_slice(&id[..4]);
+            u32::from_ne_bytes(buf)
+        }))
+    }
+
+    /// Returns an owned handle to the inner map stored at `key` in a [`MapType::ArrayOfMaps`]
+    /// or [`MapType::HashOfMaps`].
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements.
+    pub fn lookup_inner_map(&self, key: &[u8]) -> Result<Option<Map>> {
+        match self.lookup_inner_map_id(key)? {
+            Some(id) => Ok(Some(Map::from_id(id)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn check_map_in_map(&self) -> Result<()> {
+        match self.map_type() {
+            MapType::ArrayOfMaps | MapType::HashOfMaps => Ok(()),
+            ty => Err(Error::InvalidInput(format!(
+                "operation is only supported for ArrayOfMaps and HashOfMaps (type of the map is {})",
+                ty
+            ))),
         }
     }
 
+    /// Returns one value per possible cpu as `Vec` of `Vec` of `u8` for per-cpu maps.
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements. Each returned value has exactly
+    /// [`Map::value_size()`] elements.
+    ///
+    /// For normal maps, [`Map::lookup()`] must be used instead.
+    pub fn lookup_percpu(&self, key: &[u8], flags: MapFlags) -> Result<Option<Vec<Vec<u8>>>> {
+        if !self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "lookup() must be used for maps that are not per-cpu (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        if key.len() != self.key_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "key_size {} != {}",
+                key.len(),
+                self.key_size()
+            )));
+        };
+
+        let ncpu = util::num_possible_cpus()?;
+        let aligned_val_size = self.percpu_aligned_value_size();
+        let val_size = ncpu * aligned_val_size;
+
+        let mut out: Vec<u8> = Vec::with_capacity(val_size);
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_lookup_elem_flags(
+                self.fd,
+                key.as_ptr() as *const c_void,
+                out.as_mut_ptr() as *mut c_void,
+                flags.bits,
+            )
+        };
+
+        if ret == 0 {
+            unsafe {
+                out.set_len(val_size);
+            }
+
+            Ok(Some(
+                out.chunks_exact(aligned_val_size)
+                    .map(|val| val[..self.value_size() as usize].to_vec())
+                    .collect(),
+            ))
+        } else {
+            let errno = errno::errno();
+            if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                Ok(None)
+            } else {
+                Err(Error::System(errno)).context("lookup", &self.name)
+            }
+        }
+    }
+
+    /// Update an element in a per-cpu map with one value per possible cpu.
+    ///
+    /// `key` must have exactly [`Map::key_size()`] elements. `values` must have one element per
+    /// possible cpu (see [`num_possible_cpus()`]), and each element must have exactly
+    /// [`Map::value_size()`] elements.
+    ///
+    /// For normal maps, [`Map::update()`] must be used instead.
+    pub fn update_percpu(&mut self, key: &[u8], values: &[Vec<u8>], flags: MapFlags) -> Result<()> {
+        if !self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "update() must be used for maps that are not per-cpu (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        if key.len() != self.key_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "key_size {} != {}",
+                key.len(),
+                self.key_size()
+            )));
+        };
+
+        let ncpu = util::num_possible_cpus()?;
+        if values.len() != ncpu {
+            return Err(Error::InvalidInput(format!(
+                "number of values {} != number of cpus {}",
+                values.len(),
+                ncpu
+            )));
+        };
+
+        let aligned_val_size = self.percpu_aligned_value_size();
+        let mut value_buf = vec![0; ncpu * aligned_val_size];
+
+        for (i, val) in values.iter().enumerate() {
+            if val.len() != self.value_size() as usize {
+                return Err(Error::InvalidInput(format!(
+                    "value_size {} != {}",
+                    val.len(),
+                    self.value_size()
+                )));
+            }
+
+            let offset = i * aligned_val_size;
+            value_buf[offset..offset + val.len()].copy_from_slice(val);
+        }
+
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_elem(
+                self.fd,
+                key.as_ptr() as *const c_void,
+                value_buf.as_ptr() as *const c_void,
+                flags.bits,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(Error::System(errno::errno())).context("update", &self.name)
+        }
+    }
+
+    /// The kernel stores each per-cpu value rounded up to 8 bytes.
+    fn percpu_aligned_value_size(&self) -> usize {
+        (self.value_size() as usize + 7) & !7
+    }
+
+    /// Returns an iterator over `(key, value)` pairs in this map, fetching up to `batch_size`
+    /// entries from the kernel per syscall.
+    ///
+    /// `elem_flags` is applied to each element (e.g. [`MapFlags::LOCK`]) and `flags` to the batch
+    /// operation as a whole.
+    ///
+    /// Kernels without support for batch operations are handled transparently by falling back to
+    /// one lookup per element. Per-cpu maps are not supported.
+    pub fn lookup_batch(
+        &self,
+        batch_size: u32,
+        elem_flags: MapFlags,
+        flags: MapFlags,
+    ) -> Result<BatchedMapIter<'_>> {
+        BatchedMapIter::new(self, false, batch_size, elem_flags, flags)
+    }
+
+    /// Same as [`Map::lookup_batch()`] except this also deletes every returned entry from the
+    /// map.
+    pub fn lookup_and_delete_batch(
+        &mut self,
+        batch_size: u32,
+        elem_flags: MapFlags,
+        flags: MapFlags,
+    ) -> Result<BatchedMapIter<'_>> {
+        BatchedMapIter::new(self, true, batch_size, elem_flags, flags)
+    }
+
+    /// Update multiple elements with a single syscall.
+    ///
+    /// `keys` and `values` are the concatenation of the individual keys and values: `keys` must
+    /// have a multiple of [`Map::key_size()`] elements and `values` must have exactly as many
+    /// [`Map::value_size()`] sized values as there are keys.
+    ///
+    /// Kernels without support for batch operations are handled transparently by falling back to
+    /// one update per element. Per-cpu maps are not supported.
+    pub fn update_batch(
+        &mut self,
+        keys: &[u8],
+        values: &[u8],
+        elem_flags: MapFlags,
+        flags: MapFlags,
+    ) -> Result<()> {
+        let count = self.batch_count(keys)?;
+
+        if values.len() != count * self.value_size() as usize {
+            return Err(Error::InvalidInput(format!(
+                "values length {} != {} * value_size {}",
+                values.len(),
+                count,
+                self.value_size()
+            )));
+        }
+
+        if count == 0 {
+            return Ok(());
+        }
+
+        self.check_lock_flag(elem_flags)?;
+
+        let opts = batch_opts(elem_flags, flags);
+        let mut count = count as u32;
+        let ret = unsafe {
+            libbpf_sys::bpf_map_update_batch(
+                self.fd,
+                keys.as_ptr() as *mut c_void,
+                values.as_ptr() as *mut c_void,
+                &mut count,
+                &opts,
+            )
+        };
+
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let errno = errno::errno();
+        if errno::Errno::from_i32(errno) != errno::Errno::EINVAL || batch_supported(self.fd) {
+            return Err(Error::System(errno)).context("batch update", &self.name);
+        }
+
+        // Kernel does not support batch operations
+        for (key, value) in keys
+            .chunks_exact(self.key_size() as usize)
+            .zip(values.chunks_exact(self.value_size() as usize))
+        {
+            self.update(key, value, elem_flags)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete multiple elements with a single syscall.
+    ///
+    /// `keys` is the concatenation of the individual keys and must have a multiple of
+    /// [`Map::key_size()`] elements.
+    ///
+    /// Kernels without support for batch operations are handled transparently by falling back to
+    /// one delete per element. Per-cpu maps are not supported.
+    pub fn delete_batch(
+        &mut self,
+        keys: &[u8],
+        elem_flags: MapFlags,
+        flags: MapFlags,
+    ) -> Result<()> {
+        let count = self.batch_count(keys)?;
+        if count == 0 {
+            return Ok(());
+        }
+
+        let opts = batch_opts(elem_flags, flags);
+        let mut count = count as u32;
+        let ret = unsafe {
+            libbpf_sys::bpf_map_delete_batch(
+                self.fd,
+                keys.as_ptr() as *mut c_void,
+                &mut count,
+                &opts,
+            )
+        };
+
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let errno = errno::errno();
+        if errno::Errno::from_i32(errno) != errno::Errno::EINVAL || batch_supported(self.fd) {
+            return Err(Error::System(errno)).context("batch delete from", &self.name);
+        }
+
+        // Kernel does not support batch operations
+        for key in keys.chunks_exact(self.key_size() as usize) {
+            self.delete(key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Validates `keys` for a batch operation and returns the number of keys it contains.
+    fn batch_count(&self, keys: &[u8]) -> Result<usize> {
+        if self.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "batch operations are not supported for per-cpu maps (type of the map is {})",
+                self.map_type(),
+            )));
+        }
+
+        if self.key_size() == 0
+            || !keys
+                .chunks_exact(self.key_size() as usize)
+                .remainder()
+                .is_empty()
+        {
+            return Err(Error::InvalidInput(format!(
+                "keys length {} is not a multiple of key_size {}",
+                keys.len(),
+                self.key_size()
+            )));
+        }
+
+        Ok(keys.len() / self.key_size() as usize)
+    }
+
+    /// Memory map an array map created with `BPF_F_MMAPABLE` for read-only access.
+    ///
+    /// [`Map::value_size()`] must match the size of `T`. See [`OpenMap::set_map_flags()`] to
+    /// make a map mmap'able before it is created.
+    pub fn mmap<T: Pod>(&self) -> Result<MmapArray<'_, T>> {
+        MmapArray::new(self)
+    }
+
+    /// Same as [`Map::mmap()`] except the returned view can be written to.
+    pub fn mmap_mut<T: Pod>(&mut self) -> Result<MmapArrayMut<'_, T>> {
+        MmapArrayMut::new(self)
+    }
+
     /// Returns an iterator over keys in this map
     ///
     /// Note that if the map is not stable (stable meaning no updates or deletes) during iteration,
@@ -319,6 +1083,30 @@ impl Map {
     pub fn keys(&self) -> MapKeyIter {
         MapKeyIter::new(self, self.key_size())
     }
+
+    /// Returns an iterator over `(key, value)` pairs in this map.
+    ///
+    /// Unlike [`Map::keys()`], errors are returned from the iterator instead of ending iteration.
+    /// Keys deleted concurrently are skipped and every key is returned at most once, even if the
+    /// kernel restarts iteration from the beginning because the previous key was deleted.
+    ///
+    /// To do so, the iterator remembers every key it returned, using memory proportional to the
+    /// number of entries. Each restart walks the already returned keys again, so iterating while
+    /// many keys are deleted concurrently can take time quadratic in the number of entries.
+    ///
+    /// Values are looked up with [`Map::lookup()`], so per-cpu maps are not supported.
+    pub fn iter(&self) -> MapIter<'_> {
+        MapIter::new(self)
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        // Maps that are part of an object are closed along with the object
+        if self.ptr.is_null() {
+            let _ = unistd::close(self.fd);
+        }
+    }
 }
 
 #[rustfmt::skip]
@@ -371,6 +1159,19 @@ pub enum MapType {
     Unknown = u32::MAX,
 }
 
+impl MapType {
+    /// Returns if the map is of one of the per-cpu types.
+    pub fn is_percpu(&self) -> bool {
+        matches!(
+            self,
+            MapType::PercpuArray
+                | MapType::PercpuHash
+                | MapType::LruPercpuHash
+                | MapType::PercpuCgroupStorage
+        )
+    }
+}
+
 pub struct MapKeyIter<'a> {
     map: &'a Map,
     prev: Option<Vec<u8>>,
@@ -404,3 +1205,389 @@ impl<'a> Iterator for MapKeyIter<'a> {
         }
     }
 }
+
+/// Iterator over `(key, value)` pairs of a [`Map`].
+///
+/// Created by [`Map::iter()`].
+pub struct MapIter<'a> {
+    map: &'a Map,
+    prev: Option<Vec<u8>>,
+    /// Keys already returned. The kernel restarts iteration from the first key if the previous
+    /// key was deleted, so this is needed to avoid returning duplicates.
+    seen: HashSet<Vec<u8>>,
+    done: bool,
+}
+
+impl<'a> MapIter<'a> {
+    fn new(map: &'a Map) -> Self {
+        Self {
+            map,
+            prev: None,
+            seen: HashSet::new(),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let prev = self.prev.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+            let mut key = vec![0; self.map.key_size() as usize];
+
+            let ret = unsafe {
+                libbpf_sys::bpf_map_get_next_key(
+                    self.map.fd(),
+                    prev as *const c_void,
+                    key.as_mut_ptr() as *mut c_void,
+                )
+            };
+            if ret != 0 {
+                self.done = true;
+
+                let errno = errno::errno();
+                if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                    return None;
+                }
+                return Some(Err(Error::System(errno)).context("iterate", &self.map.name));
+            }
+
+            self.prev = Some(key.clone());
+            if self.seen.contains(&key) {
+                continue;
+            }
+
+            match self.map.lookup(&key, MapFlags::ANY) {
+                Ok(Some(value)) => {
+                    self.seen.insert(key.clone());
+                    return Some(Ok((key, value)));
+                }
+                // Key was deleted since we obtained it. Continuing from it restarts iteration.
+                Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[repr(C)]
+struct BtfMember {
+    name_off: u32,
+    type_: u32,
+    offset: u32,
+}
+
+fn btf_kind(t: &libbpf_sys::btf_type) -> u32 {
+    (t.info >> 24) & 0x1f
+}
+
+/// Returns whether `type_id` is a struct with a `struct bpf_spin_lock` member.
+///
+/// The kernel only supports spin locks as direct members of the map value struct, so nested
+/// structs are not searched.
+fn btf_struct_has_spin_lock(btf: *const libbpf_sys::btf, type_id: u32) -> bool {
+    let resolve = |id: u32| -> Option<&libbpf_sys::btf_type> {
+        let id = unsafe { libbpf_sys::btf__resolve_type(btf, id) };
+        if id < 0 {
+            return None;
+        }
+
+        let t = unsafe { libbpf_sys::btf__type_by_id(btf, id as u32) };
+        if t.is_null() {
+            return None;
+        }
+
+        Some(unsafe { &*t })
+    };
+
+    let value = match resolve(type_id) {
+        Some(t) if btf_kind(t) == libbpf_sys::BTF_KIND_STRUCT => t,
+        _ => return false,
+    };
+
+    // Members immediately follow the `btf_type` they belong to
+    let vlen = (value.info & 0xffff) as usize;
+    let members = unsafe { (value as *const libbpf_sys::btf_type).add(1) as *const BtfMember };
+
+    (0..vlen).any(|i| {
+        let member = unsafe { &*members.add(i) };
+        match resolve(member.type_) {
+            Some(t) if btf_kind(t) == libbpf_sys::BTF_KIND_STRUCT => {
+                let name = unsafe { libbpf_sys::btf__name_by_offset(btf, t.name_off) };
+                matches!(util::c_ptr_to_string(name).as_deref(), Ok("bpf_spin_lock"))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Returns whether the kernel supports batch operations, so an `EINVAL` from one can be told
+/// apart from invalid arguments. Kernels without them reject the unknown command before doing
+/// anything, while others accept an empty batch.
+fn batch_supported(fd: i32) -> bool {
+    let opts = batch_opts(MapFlags::ANY, MapFlags::ANY);
+    let mut count = 0;
+    let ret = unsafe {
+        libbpf_sys::bpf_map_lookup_batch(
+            fd,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut count,
+            &opts,
+        )
+    };
+
+    ret == 0 || errno::Errno::from_i32(errno::errno()) != errno::Errno::EINVAL
+}
+
+fn batch_opts(elem_flags: MapFlags, flags: MapFlags) -> libbpf_sys::bpf_map_batch_opts {
+    libbpf_sys::bpf_map_batch_opts {
+        sz: mem::size_of::<libbpf_sys::bpf_map_batch_opts>() as libbpf_sys::size_t,
+        elem_flags: elem_flags.bits,
+        flags: flags.bits,
+    }
+}
+
+/// Iterator over `(key, value)` pairs of a [`Map`], fetched in batches.
+///
+/// Created by [`Map::lookup_batch()`] and [`Map::lookup_and_delete_batch()`].
+pub struct BatchedMapIter<'a> {
+    map: &'a Map,
+    delete: bool,
+    batch_size: u32,
+    elem_flags: MapFlags,
+    flags: MapFlags,
+    /// Batch token of the previous syscall. `None` before the first syscall.
+    in_batch: Option<Vec<u8>>,
+    out_batch: Vec<u8>,
+    keys: Vec<u8>,
+    values: Vec<u8>,
+    /// Number of entries fetched by the last syscall
+    count: usize,
+    /// Index of the next entry to return from the last fetched batch
+    index: usize,
+    /// Set once there is nothing left to fetch from the kernel
+    done: bool,
+    /// Set if the kernel does not support batch operations
+    fallback: bool,
+    prev_key: Option<Vec<u8>>,
+}
+
+impl<'a> BatchedMapIter<'a> {
+    fn new(
+        map: &'a Map,
+        delete: bool,
+        batch_size: u32,
+        elem_flags: MapFlags,
+        flags: MapFlags,
+    ) -> Result<Self> {
+        if map.map_type().is_percpu() {
+            return Err(Error::InvalidInput(format!(
+                "batch operations are not supported for per-cpu maps (type of the map is {})",
+                map.map_type(),
+            )));
+        }
+
+        if batch_size == 0 {
+            return Err(Error::InvalidInput("batch_size must not be 0".to_string()));
+        }
+
+        map.check_lock_flag(elem_flags)?;
+
+        let mut iter = Self {
+            map,
+            delete,
+            batch_size: 0,
+            elem_flags,
+            flags,
+            in_batch: None,
+            // Hash maps use a u32 bucket index as batch token, other maps use a key
+            out_batch: vec![0; map.key_size().max(4) as usize],
+            keys: Vec::new(),
+            values: Vec::new(),
+            count: 0,
+            index: 0,
+            done: false,
+            fallback: false,
+            prev_key: None,
+        };
+        iter.resize(batch_size);
+
+        Ok(iter)
+    }
+
+    fn resize(&mut self, batch_size: u32) {
+        self.batch_size = batch_size;
+        self.keys
+            .resize(batch_size as usize * self.map.key_size() as usize, 0);
+        self.values
+            .resize(batch_size as usize * self.map.value_size() as usize, 0);
+    }
+
+    /// Fetches the next batch of entries from the kernel.
+    fn fetch(&mut self) -> Result<()> {
+        loop {
+            let opts = batch_opts(self.elem_flags, self.flags);
+            let in_batch = self
+                .in_batch
+                .as_mut()
+                .map_or(ptr::null_mut(), |b| b.as_mut_ptr() as *mut c_void);
+            let mut count = self.batch_size;
+
+            let ret = unsafe {
+                let f = if self.delete {
+                    libbpf_sys::bpf_map_lookup_and_delete_batch
+                } else {
+                    libbpf_sys::bpf_map_lookup_batch
+                };
+                f(
+                    self.map.fd(),
+                    in_batch,
+                    self.out_batch.as_mut_ptr() as *mut c_void,
+                    self.keys.as_mut_ptr() as *mut c_void,
+                    self.values.as_mut_ptr() as *mut c_void,
+                    &mut count,
+                    &opts,
+                )
+            };
+
+            if ret != 0 {
+                let errno = errno::errno();
+                match errno::Errno::from_i32(errno) {
+                    // Last batch. `count` entries were still returned.
+                    errno::Errno::ENOENT => self.done = true,
+                    // A single hash bucket holds more entries than fit into the batch
+                    errno::Errno::ENOSPC if count == 0 => match self.batch_size.checked_mul(2) {
+                        Some(batch_size) => {
+                            self.resize(batch_size);
+                            continue;
+                        }
+                        None => {
+                            return Err(Error::System(errno))
+                                .context("batch lookup from", &self.map.name)
+                        }
+                    },
+                    // Kernel does not support batch operations
+                    errno::Errno::EINVAL
+                        if self.in_batch.is_none() && !batch_supported(self.map.fd()) =>
+                    {
+                        self.fallback = true;
+                        return Ok(());
+                    }
+                    _ => {
+                        return Err(Error::System(errno))
+                            .context("batch lookup from", &self.map.name)
+                    }
+                }
+            }
+
+            self.count = count as usize;
+            self.index = 0;
+            self.in_batch = Some(self.out_batch.clone());
+
+            return Ok(());
+        }
+    }
+
+    /// Per-element iteration for kernels without batch operations.
+    fn next_fallback(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            // Deleting the previous key restarts iteration, so always start from the beginning
+            let prev = match (&self.prev_key, self.delete) {
+                (Some(prev), false) => prev.as_ptr(),
+                _ => ptr::null(),
+            };
+
+            let mut key = vec![0; self.map.key_size() as usize];
+            let ret = unsafe {
+                libbpf_sys::bpf_map_get_next_key(
+                    self.map.fd(),
+                    prev as *const c_void,
+                    key.as_mut_ptr() as *mut c_void,
+                )
+            };
+            if ret != 0 {
+                self.done = true;
+
+                let errno = errno::errno();
+                if errno::Errno::from_i32(errno) == errno::Errno::ENOENT {
+                    return None;
+                }
+                return Some(Err(Error::System(errno)).context("iterate", &self.map.name));
+            }
+
+            let value = match self.map.lookup(&key, self.elem_flags) {
+                Ok(Some(value)) => value,
+                // Key was deleted since we obtained it
+                Ok(None) => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+
+            if self.delete {
+                let ret = unsafe {
+                    libbpf_sys::bpf_map_delete_elem(self.map.fd(), key.as_ptr() as *const c_void)
+                };
+                if ret != 0 {
+                    let errno = errno::errno();
+                    if errno::Errno::from_i32(errno) != errno::Errno::ENOENT {
+                        self.done = true;
+                        return Some(
+                            Err(Error::System(errno)).context("delete from", &self.map.name),
+                        );
+                    }
+                }
+            }
+
+            self.prev_key = Some(key.clone());
+            return Some(Ok((key, value)));
+        }
+    }
+}
+
+impl<'a> Iterator for BatchedMapIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.index < self.count {
+                let key_size = self.map.key_size() as usize;
+                let value_size = self.map.value_size() as usize;
+                let i = self.index;
+                self.index += 1;
+
+                return Some(Ok((
+                    self.keys[i * key_size..(i + 1) * key_size].to_vec(),
+                    self.values[i * value_size..(i + 1) * value_size].to_vec(),
+                )));
+            }
+
+            if self.fallback {
+                if self.done {
+                    return None;
+                }
+                return self.next_fallback();
+            }
+
+            if self.done {
+                return None;
+            }
+
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use core::ffi::c_void;
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::ptr;
+
+use nix::sys::mman::{mmap, munmap, MapFlags as MmapFlags, ProtFlags};
+use nix::unistd::{sysconf, SysconfVar};
+
+use crate::query::MapInfo;
+use crate::*;
+
+/// An mmap'ed region backing an array map. Unmapped on drop.
+struct RawMmap {
+    ptr: *mut c_void,
+    size: usize,
+    /// Size of each element as laid out by the kernel
+    stride: usize,
+    len: usize,
+}
+
+impl RawMmap {
+    fn new<T: Pod>(map: &Map, writable: bool) -> Result<Self> {
+        if map.map_type() != MapType::Array {
+            return Err(Error::InvalidInput(format!(
+                "Only Array maps can be mmap'ed (type of the map is {})",
+                map.map_type()
+            )));
+        }
+
+        if map.value_size() as usize != size_of::<T>() {
+            return Err(Error::InvalidInput(format!(
+                "map value_size {} != value type size {}",
+                map.value_size(),
+                size_of::<T>()
+            )));
+        }
+
+        // Elements are only guaranteed to be 8 byte aligned
+        if align_of::<T>() > 8 {
+            return Err(Error::InvalidInput(format!(
+                "alignment {} of value type is larger than 8",
+                align_of::<T>()
+            )));
+        }
+
+        let info = MapInfo::from_fd(map.fd())?;
+        if info.map_flags & libbpf_sys::BPF_F_MMAPABLE == 0 {
+            return Err(Error::InvalidInput(format!(
+                "Map {} was not created with BPF_F_MMAPABLE",
+                map.name()
+            )));
+        }
+
+        let page_size = match sysconf(SysconfVar::PAGE_SIZE) {
+            Ok(Some(size)) => size as usize,
+            _ => return Err(Error::Internal("Failed to get page size".to_string())),
+        };
+
+        // The kernel rounds each array element up to 8 bytes
+        let stride = (map.value_size() as usize + 7) & !7;
+        let len = info.max_entries as usize;
+        let size = (stride * len + page_size - 1) & !(page_size - 1);
+
+        let prot = if writable {
+            ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+        } else {
+            ProtFlags::PROT_READ
+        };
+
+        let ptr = unsafe {
+            mmap(
+                ptr::null_mut(),
+                size,
+                prot,
+                MmapFlags::MAP_SHARED,
+                map.fd(),
+                0,
+            )
+        }
+        .map_err(|e| Error::System(e.as_errno().map_or(0, |e| e as i32)))
+        .context("mmap", map.name())?;
+
+        Ok(Self {
+            ptr,
+            size,
+            stride,
+            len,
+        })
+    }
+
+    fn elem_ptr<T>(&self, idx: usize) -> Option<*mut T> {
+        if idx >= self.len {
+            return None;
+        }
+
+        // Elements are 8 byte aligned and the mapping is page aligned, so the pointer is
+        // suitably aligned for `T` as checked in `new()`.
+        Some(unsafe { self.ptr.add(idx * self.stride) } as *mut T)
+    }
+}
+
+impl Drop for RawMmap {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.ptr, self.size) };
+    }
+}
+
+/// Read-only view of an array [`Map`] created with `BPF_F_MMAPABLE`.
+///
+/// Created by [`Map::mmap()`]. Elements are read directly from the memory shared with BPF
+/// programs, without syscalls.
+///
+/// Because BPF programs may modify the memory at any time, elements are copied out with volatile
+/// reads instead of being exposed as a `&[T]`.
+pub struct MmapArray<'a, T: Pod> {
+    mmap: RawMmap,
+    _map: PhantomData<(&'a Map, T)>,
+}
+
+impl<'a, T: Pod> MmapArray<'a, T> {
+    pub(crate) fn new(map: &'a Map) -> Result<Self> {
+        Ok(Self {
+            mmap: RawMmap::new::<T>(map, false)?,
+            _map: PhantomData,
+        })
+    }
+
+    /// Number of elements in the array.
+    pub fn len(&self) -> usize {
+        self.mmap.len
+    }
+
+    /// Returns whether the array has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.mmap.len == 0
+    }
+
+    /// Returns a copy of the element at `idx`, or `None` if out of bounds.
+    pub fn get(&self, idx: usize) -> Option<T> {
+        self.mmap
+            .elem_ptr::<T>(idx)
+            .map(|p| unsafe { ptr::read_volatile(p) })
+    }
+
+    /// Returns an iterator over copies of all elements.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+}
+
+/// Mutable view of an array [`Map`] created with `BPF_F_MMAPABLE`.
+///
+/// Created by [`Map::mmap_mut()`]. See [`MmapArray`] for details.
+pub struct MmapArrayMut<'a, T: Pod> {
+    mmap: RawMmap,
+    _map: PhantomData<(&'a mut Map, T)>,
+}
+
+impl<'a, T: Pod> MmapArrayMut<'a, T> {
+    pub(crate) fn new(map: &'a mut Map) -> Result<Self> {
+        Ok(Self {
+            mmap: RawMmap::new::<T>(map, true)?,
+            _map: PhantomData,
+        })
+    }
+
+    /// Number of elements in the array.
+    pub fn len(&self) -> usize {
+        self.mmap.len
+    }
+
+    /// Returns whether the array has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.mmap.len == 0
+    }
+
+    /// Returns a copy of the element at `idx`, or `None` if out of bounds.
+    pub fn get(&self, idx: usize) -> Option<T> {
+        self.mmap
+            .elem_ptr::<T>(idx)
+            .map(|p| unsafe { ptr::read_volatile(p) })
+    }
+
+    /// Overwrites the element at `idx` with `val`.
+    pub fn set(&mut self, idx: usize, val: T) -> Result<()> {
+        match self.mmap.elem_ptr::<T>(idx) {
+            Some(p) => {
+                unsafe { ptr::write_volatile(p, val) };
+                Ok(())
+            }
+            None => Err(Error::InvalidInput(format!(
+                "index {} out of bounds (len {})",
+                idx,
+                self.len()
+            ))),
+        }
+    }
+
+    /// Returns an iterator over copies of all elements.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+}
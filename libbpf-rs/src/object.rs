@@ -1,18 +1,81 @@
 use core::ffi::c_void;
-use std::collections::HashMap;
-use std::ffi::CStr;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::Write;
 use std::mem;
 use std::os::raw::c_char;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::Path;
 use std::ptr;
 
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+use crate::print;
+use crate::skeleton::ObjectSkeletonConfig;
 use crate::util;
 use crate::*;
 
+/// Value of a kernel config option as seen by `extern ... __kconfig` variables.
+///
+/// See [`ObjectBuilder::kconfig_option()`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum KconfigValue {
+    Yes,
+    Module,
+    No,
+    Int(i64),
+    Str(String),
+}
+
+/// BTF used as target for CO-RE relocations instead of the BTF of the running kernel.
+#[derive(Clone)]
+enum CustomBtf {
+    Path(CString),
+    Data(Vec<u8>),
+}
+
+impl CustomBtf {
+    /// Calls `f` with a path to the BTF that stays valid during the call.
+    fn with_path<F: FnOnce(*const c_char) -> i32>(&self, f: F) -> Result<i32> {
+        match self {
+            CustomBtf::Path(path) => Ok(f(path.as_ptr())),
+            CustomBtf::Data(data) => {
+                // libbpf can only parse BTF from a file, so hand it an in-memory one
+                let name = util::str_to_cstring("btf")?;
+                let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)
+                    .map_err(|e| Error::System(e.as_errno().map_or(0, |e| e as i32)))
+                    .context("create memfd for", "custom BTF")?;
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                file.write_all(data)
+                    .map_err(|e| Error::System(e.raw_os_error().unwrap_or(0)))
+                    .context("write custom BTF to memfd", fd)?;
+
+                let path = util::str_to_cstring(&format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+                Ok(f(path.as_ptr()))
+            }
+        }
+    }
+}
+
+/// Verifier log settings passed from [`ObjectBuilder`] to [`OpenObject::load()`].
+#[derive(Clone, Copy, Default)]
+struct VerifierLogConfig {
+    level: u32,
+    truncate_len: Option<usize>,
+}
+
 /// Builder for creating an [`OpenObject`]. Typically the entry point into libbpf-rs.
 pub struct ObjectBuilder {
     name: String,
     relaxed_maps: bool,
+    pin_root_path: Option<CString>,
+    kconfig: String,
+    kconfig_options: BTreeMap<String, String>,
+    /// `kconfig` and `kconfig_options` combined into the format understood by libbpf
+    kconfig_c: Option<CString>,
+    custom_btf: Option<CustomBtf>,
+    verifier_log: VerifierLogConfig,
 }
 
 impl ObjectBuilder {
@@ -28,29 +91,127 @@ impl ObjectBuilder {
         self
     }
 
-    /// Option to print debug output to stderr.
-    pub fn debug(&mut self, dbg: bool) -> &mut Self {
-        extern "C" fn cb(
-            _level: libbpf_sys::libbpf_print_level,
-            fmtstr: *const c_char,
-            va_list: *mut libbpf_sys::__va_list_tag,
-        ) -> i32 {
-            match unsafe { vsprintf::vsprintf(fmtstr, va_list) } {
-                Ok(s) => {
-                    print!("{}", s);
-                    0
-                }
-                Err(e) => {
-                    eprintln!("Failed to parse libbpf output: {}", e);
-                    1
+    /// Directory in bpffs under which maps declared with `LIBBPF_PIN_BY_NAME` are pinned and
+    /// looked up for reuse on load. Defaults to `/sys/fs/bpf`.
+    pub fn pin_root_path<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        self.pin_root_path = Some(util::path_to_cstring(path)?);
+        Ok(self)
+    }
+
+    /// Kernel config in `/proc/config.gz` format (e.g. `CONFIG_BPF=y` lines) used to resolve
+    /// `extern ... __kconfig` variables instead of the config of the running kernel.
+    ///
+    /// Options not set in `kconfig` are still resolved from the running kernel.
+    pub fn kconfig<T: AsRef<str>>(&mut self, kconfig: T) -> Result<&mut Self> {
+        self.kconfig = kconfig.as_ref().to_string();
+        self.update_kconfig()?;
+        Ok(self)
+    }
+
+    /// Override a single kernel config option, e.g. `kconfig_option("CONFIG_HZ",
+    /// KconfigValue::Int(1000))`.
+    ///
+    /// Overrides take precedence over the config set with [`ObjectBuilder::kconfig()`].
+    pub fn kconfig_option<T: AsRef<str>>(
+        &mut self,
+        name: T,
+        value: KconfigValue,
+    ) -> Result<&mut Self> {
+        let name = name.as_ref();
+        if !name.starts_with("CONFIG_")
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(Error::InvalidInput(format!(
+                "{} is not a valid kernel config option name",
+                name
+            )));
+        }
+
+        let value = match value {
+            KconfigValue::Yes => "y".to_string(),
+            KconfigValue::Module => "m".to_string(),
+            KconfigValue::No => "n".to_string(),
+            KconfigValue::Int(v) => v.to_string(),
+            KconfigValue::Str(s) => {
+                if s.contains(['"', '\n']) {
+                    return Err(Error::InvalidInput(format!(
+                        "Invalid string value for {}: {}",
+                        name, s
+                    )));
                 }
+                format!("\"{}\"", s)
             }
+        };
+
+        self.kconfig_options.insert(name.to_string(), value);
+        self.update_kconfig()?;
+        Ok(self)
+    }
+
+    fn update_kconfig(&mut self) -> Result<()> {
+        // libbpf uses the first value it finds for an option, so overrides go first
+        let mut kconfig = String::new();
+        for (name, value) in &self.kconfig_options {
+            kconfig.push_str(&format!("{}={}\n", name, value));
         }
+        kconfig.push_str(&self.kconfig);
+
+        self.kconfig_c = if kconfig.is_empty() {
+            None
+        } else {
+            Some(util::str_to_cstring(&kconfig)?)
+        };
+
+        Ok(())
+    }
+
+    /// Use the BTF in the file at `path` instead of the BTF of the running kernel for CO-RE
+    /// relocations, e.g. for kernels without `/sys/kernel/btf/vmlinux`.
+    ///
+    /// The file may contain raw BTF or be an ELF file with a `.BTF` section.
+    pub fn btf_custom_path<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        self.custom_btf = Some(CustomBtf::Path(util::path_to_cstring(path)?));
+        Ok(self)
+    }
+
+    /// Like [`ObjectBuilder::btf_custom_path()`], but with the BTF contents provided in memory.
+    pub fn btf_custom_data(&mut self, data: &[u8]) -> &mut Self {
+        self.custom_btf = Some(CustomBtf::Data(data.to_vec()));
+        self
+    }
+
+    /// Verifier log level for all programs, as in the `log_level` attribute of `BPF_PROG_LOAD`.
+    ///
+    /// The verifier log of a program that fails to load is always returned in
+    /// [`Error::ProgramRejected`]. A non-zero level makes the log more verbose and also prints
+    /// the log of successfully loaded programs as debug output (see [`set_print()`]).
+    pub fn verifier_log_level(&mut self, level: u32) -> &mut Self {
+        self.verifier_log.level = level;
+        self
+    }
+
+    /// Truncate the verifier log returned in [`Error::ProgramRejected`] to its last `len` bytes,
+    /// which hold the explanation of the error.
+    ///
+    /// This is a limit on the output only. It does not change the log buffer programs are loaded
+    /// with, which libbpf allocates and grows on its own until the whole log fits.
+    pub fn truncate_verifier_log(&mut self, len: usize) -> &mut Self {
+        self.verifier_log.truncate_len = Some(len);
+        self
+    }
 
+    /// Option to print debug output to stdout.
+    ///
+    /// This replaces any callback installed with [`set_print()`]. Disabling debug output disables
+    /// all libbpf output.
+    pub fn debug(&mut self, dbg: bool) -> &mut Self {
         if dbg {
-            unsafe { libbpf_sys::libbpf_set_print(Some(cb)) };
+            set_print(
+                Some(Box::new(|_, msg| print!("{}", msg))),
+                PrintLevel::Debug,
+            );
         } else {
-            unsafe { libbpf_sys::libbpf_set_print(None) };
+            set_print(None, PrintLevel::Debug);
         }
 
         self
@@ -64,9 +225,12 @@ impl ObjectBuilder {
             object_name: name,
             relaxed_maps: self.relaxed_maps,
             relaxed_core_relocs: false,
-            pin_root_path: ptr::null(),
+            pin_root_path: self
+                .pin_root_path
+                .as_ref()
+                .map_or(ptr::null(), |p| p.as_ptr()),
             attach_prog_fd: 0,
-            kconfig: ptr::null(),
+            kconfig: self.kconfig_c.as_ref().map_or(ptr::null(), |c| c.as_ptr()),
         }
     }
 
@@ -93,10 +257,10 @@ impl ObjectBuilder {
         let obj = unsafe { libbpf_sys::bpf_object__open_file(path_ptr, &opts) };
         let err = unsafe { libbpf_sys::libbpf_get_error(obj as *const _) };
         if err != 0 {
-            return Err(Error::System(err as i32));
+            return Err(Error::System(err as i32)).context("open object file", path_str);
         }
 
-        OpenObject::new(obj)
+        self.finish_open(obj)
     }
 
     pub fn open_memory<T: AsRef<str>>(&mut self, name: T, mem: &[u8]) -> Result<OpenObject> {
@@ -121,10 +285,32 @@ impl ObjectBuilder {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(obj as *const _) };
         if err != 0 {
-            return Err(Error::System(err as i32));
+            return Err(Error::System(err as i32))
+                .context("open object from memory", name.to_string_lossy());
+        }
+
+        self.finish_open(obj)
+    }
+
+    /// Used for skeleton -- an end user may not consider this API stable
+    #[doc(hidden)]
+    pub fn open_skeleton(&mut self, skel_config: &mut ObjectSkeletonConfig) -> Result<OpenObject> {
+        let opts = self.opts(ptr::null());
+
+        let ret = unsafe { libbpf_sys::bpf_object__open_skeleton(skel_config.get(), &opts) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("open skeleton object", &self.name);
         }
 
-        OpenObject::new(obj)
+        self.finish_open(skel_config.object_ptr())
+    }
+
+    fn finish_open(&self, ptr: *mut libbpf_sys::bpf_object) -> Result<OpenObject> {
+        let mut obj = OpenObject::new(ptr)?;
+        obj.custom_btf = self.custom_btf.clone();
+        obj.verifier_log = self.verifier_log;
+        Ok(obj)
     }
 }
 
@@ -133,6 +319,12 @@ impl Default for ObjectBuilder {
         ObjectBuilder {
             name: String::new(),
             relaxed_maps: false,
+            pin_root_path: None,
+            kconfig: String::new(),
+            kconfig_options: BTreeMap::new(),
+            kconfig_c: None,
+            custom_btf: None,
+            verifier_log: VerifierLogConfig::default(),
         }
     }
 }
@@ -144,6 +336,8 @@ pub struct OpenObject {
     ptr: *mut libbpf_sys::bpf_object,
     maps: HashMap<String, OpenMap>,
     progs: HashMap<String, OpenProgram>,
+    custom_btf: Option<CustomBtf>,
+    verifier_log: VerifierLogConfig,
 }
 
 impl OpenObject {
@@ -152,6 +346,8 @@ impl OpenObject {
             ptr,
             maps: HashMap::new(),
             progs: HashMap::new(),
+            custom_btf: None,
+            verifier_log: VerifierLogConfig::default(),
         };
 
         // Populate obj.maps
@@ -220,7 +416,7 @@ impl OpenObject {
             let ptr = libbpf_sys::bpf_object__name(self.ptr);
             let err = libbpf_sys::libbpf_get_error(ptr as *const _);
             if err != 0 {
-                return Err(Error::System(err as i32));
+                return Err(Error::System(err as i32)).context("get name of", "object");
             }
 
             CStr::from_ptr(ptr)
@@ -273,12 +469,64 @@ impl OpenObject {
         self.progs.values_mut()
     }
 
+    fn load_ptr(&mut self) -> Result<()> {
+        let (ret, msgs) = print::capture(|| {
+            let load = |target_btf_path| {
+                let mut attr = libbpf_sys::bpf_object_load_attr {
+                    obj: self.ptr,
+                    log_level: self.verifier_log.level as i32,
+                    target_btf_path,
+                };
+                unsafe { libbpf_sys::bpf_object__load_xattr(&mut attr) }
+            };
+
+            match self.custom_btf {
+                Some(ref btf) => btf.with_path(load),
+                None => Ok(load(ptr::null())),
+            }
+        });
+
+        self.check_load(ret?, &msgs)
+    }
+
+    /// Turns the result of loading the object into an error, using the messages libbpf printed
+    /// during load to find the verifier log.
+    fn check_load(&self, ret: i32, msgs: &[(PrintLevel, String)]) -> Result<()> {
+        if ret == 0 {
+            return Ok(());
+        }
+
+        match program_rejected(msgs, self.verifier_log.truncate_len) {
+            Some(e) => Err(e),
+            // bpf_object__load() returns errno as negative, so flip
+            None => Err(Error::System(-ret)).context("load object", self.name().unwrap_or("")),
+        }
+    }
+
     /// Load the maps and programs contained in this BPF object into the system.
     pub fn load(mut self) -> Result<Object> {
-        let ret = unsafe { libbpf_sys::bpf_object__load(self.ptr) };
-        if ret != 0 {
-            // bpf_object__load() returns errno as negative, so flip
-            return Err(Error::System(-ret));
+        self.load_ptr()?;
+
+        let obj = Object::new(self.ptr)?;
+
+        // Prevent object from being closed once `self` is dropped
+        self.ptr = ptr::null_mut();
+
+        Ok(obj)
+    }
+
+    /// Used for skeleton -- an end user may not consider this API stable
+    #[doc(hidden)]
+    pub fn load_skeleton(mut self, skel_config: &mut ObjectSkeletonConfig) -> Result<Object> {
+        if self.custom_btf.is_some() || self.verifier_log.level != 0 {
+            // bpf_object__load_skeleton() cannot pass load attributes, so do its work ourselves
+            self.load_ptr()?;
+            skel_config.mmap_maps()?;
+        } else {
+            let (ret, msgs) = print::capture(|| unsafe {
+                libbpf_sys::bpf_object__load_skeleton(skel_config.get())
+            });
+            self.check_load(ret, &msgs)?;
         }
 
         let obj = Object::new(self.ptr)?;
@@ -290,6 +538,55 @@ impl OpenObject {
     }
 }
 
+// libbpf 0.4 offers no way to pass a log buffer when loading an object. It loads each program
+// with a buffer of its own and only hands the verifier log out as warnings framed by these
+// messages, so that output is where the log is recovered from. If the messages ever change, the
+// load error falls back to `Error::System`, which `test_object_program_rejected` catches.
+const LOG_BEGIN_MSG: &str = "-- BEGIN DUMP LOG ---\n";
+const LOG_END_MSG: &str = "-- END LOG --\n";
+const PROG_FAILED_PREFIX: &str = "failed to load program '";
+
+/// Extracts the verifier log of the program libbpf failed to load from the messages libbpf
+/// printed while loading.
+fn program_rejected(msgs: &[(PrintLevel, String)], truncate_len: Option<usize>) -> Option<Error> {
+    let mut name = None;
+    let mut log: Option<String> = None;
+    let mut in_log = false;
+
+    for (_, msg) in msgs {
+        let msg = msg.strip_prefix("libbpf: ").unwrap_or(msg);
+        if msg == LOG_BEGIN_MSG {
+            in_log = true;
+            log = Some(String::new());
+        } else if msg == LOG_END_MSG {
+            in_log = false;
+        } else if in_log {
+            if let Some(ref mut log) = log {
+                log.push_str(msg);
+            }
+        } else if let Some(rest) = msg.strip_prefix(PROG_FAILED_PREFIX) {
+            name = rest.split('\'').next().map(|n| n.to_string());
+        }
+    }
+
+    let log = log?;
+    let mut log = log.trim_matches('\n');
+    if let Some(truncate_len) = truncate_len {
+        if log.len() > truncate_len {
+            let mut start = log.len() - truncate_len;
+            while !log.is_char_boundary(start) {
+                start += 1;
+            }
+            log = &log[start..];
+        }
+    }
+
+    Some(Error::ProgramRejected {
+        name: name?,
+        log: log.to_string(),
+    })
+}
+
 impl Drop for OpenObject {
     fn drop(&mut self) {
         // `self.ptr` may be null if `load()` was called. This is ok: libbpf noops
@@ -345,7 +642,7 @@ impl Object {
             // Get the map fd
             let fd = unsafe { libbpf_sys::bpf_map__fd(next_ptr) };
             if fd < 0 {
-                return Err(Error::System(-fd));
+                return Err(Error::System(-fd)).context("get fd of map", name);
             }
 
             // Add the map to the hashmap
@@ -375,15 +672,15 @@ impl Object {
             let section = unsafe { libbpf_sys::bpf_program__section_name(next_ptr) };
             let section = util::c_ptr_to_string(section)?;
 
-            // Get the program fd
+            // Get the program fd. Programs with autoload disabled were not loaded and have none.
             let fd = unsafe { libbpf_sys::bpf_program__fd(next_ptr) };
-            if fd < 0 {
-                return Err(Error::System(-fd));
+            if fd < 0 && unsafe { libbpf_sys::bpf_program__autoload(next_ptr) } {
+                return Err(Error::System(-fd)).context("get fd of program", name);
             }
 
             // Add the program to the hashmap
             obj.progs
-                .insert(name.clone(), Program::new(next_ptr, name, section));
+                .insert(name.clone(), Program::new(next_ptr, obj.ptr, name, section));
             prog = next_ptr;
         }
 
@@ -445,6 +742,91 @@ impl Object {
     pub fn progs_iter_mut(&mut self) -> impl Iterator<Item = &mut Program> {
         self.progs.values_mut()
     }
+
+    /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// all maps of this object into directory `path` in bpffs. Each map is pinned under its name.
+    pub fn pin_maps<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let ret = unsafe { libbpf_sys::bpf_object__pin_maps(self.ptr, path_ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret)).context("pin maps to", path.as_ref().display())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// all maps of this object from directory `path` in bpffs.
+    pub fn unpin_maps<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let ret = unsafe { libbpf_sys::bpf_object__unpin_maps(self.ptr, path_ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret)).context("unpin maps from", path.as_ref().display())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// all programs of this object into directory `path` in bpffs. Each program is pinned under
+    /// its name.
+    pub fn pin_programs<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let ret = unsafe { libbpf_sys::bpf_object__pin_programs(self.ptr, path_ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret)).context("pin programs to", path.as_ref().display())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
+    /// all programs of this object from directory `path` in bpffs.
+    pub fn unpin_programs<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let ret = unsafe { libbpf_sys::bpf_object__unpin_programs(self.ptr, path_ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret)).context("unpin programs from", path.as_ref().display())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pin all maps and programs of this object into directory `path` in bpffs.
+    ///
+    /// See [`Object::pin_maps()`] and [`Object::pin_programs()`].
+    pub fn pin_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let ret = unsafe { libbpf_sys::bpf_object__pin(self.ptr, path_ptr) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            Err(Error::System(-ret)).context("pin object to", path.as_ref().display())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Unpin all maps and programs of this object from directory `path` in bpffs.
+    ///
+    /// See [`Object::unpin_maps()`] and [`Object::unpin_programs()`].
+    pub fn unpin_all<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.unpin_programs(&path)?;
+        self.unpin_maps(&path)
+    }
 }
 
 impl Drop for Object {
@@ -454,3 +836,48 @@ impl Drop for Object {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msgs(msgs: &[&str]) -> Vec<(PrintLevel, String)> {
+        msgs.iter()
+            .map(|msg| (PrintLevel::Warn, format!("libbpf: {}", msg)))
+            .collect()
+    }
+
+    #[test]
+    fn test_program_rejected() {
+        let msgs = msgs(&[
+            "load bpf program failed: Permission denied\n",
+            "-- BEGIN DUMP LOG ---\n",
+            "\n0: (b7) r0 = 0\nR0 !read_ok\nprocessed 1 insns\n\n",
+            "-- END LOG --\n",
+            "failed to load program 'handle_exec'\n",
+            "failed to load object 'prog'\n",
+        ]);
+
+        match program_rejected(&msgs, None) {
+            Some(Error::ProgramRejected { name, log }) => {
+                assert_eq!(name, "handle_exec");
+                assert_eq!(log, "0: (b7) r0 = 0\nR0 !read_ok\nprocessed 1 insns");
+            }
+            _ => panic!("expected ProgramRejected"),
+        }
+
+        match program_rejected(&msgs, Some(17)) {
+            Some(Error::ProgramRejected { log, .. }) => assert_eq!(log, "processed 1 insns"),
+            _ => panic!("expected ProgramRejected"),
+        }
+    }
+
+    #[test]
+    fn test_program_rejected_without_log() {
+        let msgs = msgs(&[
+            "load bpf program failed: Operation not permitted\n",
+            "failed to load program 'handle_exec'\n",
+        ]);
+        assert!(program_rejected(&msgs, None).is_none());
+    }
+}
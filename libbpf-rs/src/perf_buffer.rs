@@ -117,7 +117,7 @@ impl<'a> PerfBufferBuilder<'a> {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("create perf buffer for", self.map.name())
         } else {
             Ok(PerfBuffer {
                 ptr,
@@ -155,7 +155,7 @@ impl PerfBuffer {
     pub fn poll(&self, timeout: Duration) -> Result<()> {
         let ret = unsafe { libbpf_sys::perf_buffer__poll(self.ptr, timeout.as_millis() as i32) };
         if ret < 0 {
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("poll", "perf buffer")
         } else {
             Ok(())
         }
@@ -0,0 +1,110 @@
+use std::ffi::CStr;
+use std::fs;
+use std::mem::size_of;
+
+use nix::{errno, libc};
+
+use crate::*;
+
+/// Prefix of `struct perf_event_attr` up to and including `config2` (`PERF_ATTR_SIZE_VER1`).
+/// libbpf-sys only exposes an opaque definition.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+}
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+fn read_pmu_file(pmu: &str, file: &str) -> Result<String> {
+    let path = format!("/sys/bus/event_source/devices/{}/{}", pmu, file);
+    fs::read_to_string(&path)
+        .map_err(|e| Error::Internal(format!("Failed to read {}: {}", path, e)))
+}
+
+/// Returns the dynamic PMU type of `pmu`, e.g. `kprobe` or `uprobe`.
+fn pmu_type(pmu: &str) -> Result<u32> {
+    let ty = read_pmu_file(pmu, "type")?;
+    ty.trim()
+        .parse()
+        .map_err(|e| Error::Internal(format!("Failed to parse {} PMU type: {}", pmu, e)))
+}
+
+/// Returns the first bit of the `perf_event_attr::config` field `field` of `pmu`, e.g. the bit
+/// that turns a probe into a return probe.
+fn pmu_config_shift(pmu: &str, field: &str) -> Result<u32> {
+    // Formatted as "config:<bit>" or "config:<first bit>-<last bit>"
+    let format = read_pmu_file(pmu, &format!("format/{}", field))?;
+    format
+        .trim()
+        .strip_prefix("config:")
+        .and_then(|bits| bits.split('-').next())
+        .and_then(|bit| bit.parse().ok())
+        .ok_or_else(|| {
+            Error::Internal(format!(
+                "Unexpected {} {} format: {}",
+                pmu,
+                field,
+                format.trim()
+            ))
+        })
+}
+
+/// Open a kprobe or uprobe perf event using the kprobe/uprobe PMU.
+///
+/// For kprobes, `target` is the kernel function name and `offset` the offset into it. For
+/// uprobes, `target` is the path of the binary and `offset` the file offset of the probed
+/// instruction. `ref_ctr_offset` is the file offset of the reference counter (USDT semaphore)
+/// of a uprobe, or `0`. Returns the perf event fd.
+pub(crate) fn open_probe(
+    uprobe: bool,
+    retprobe: bool,
+    target: &CStr,
+    offset: u64,
+    ref_ctr_offset: u64,
+    pid: i32,
+) -> Result<i32> {
+    let pmu = if uprobe { "uprobe" } else { "kprobe" };
+
+    let mut attr = PerfEventAttr {
+        type_: pmu_type(pmu)?,
+        size: size_of::<PerfEventAttr>() as u32,
+        config1: target.as_ptr() as u64,
+        config2: offset,
+        ..Default::default()
+    };
+    if retprobe {
+        attr.config |= 1 << pmu_config_shift(pmu, "retprobe")?;
+    }
+    if ref_ctr_offset != 0 {
+        attr.config |= ref_ctr_offset << pmu_config_shift(pmu, "ref_ctr_offset")?;
+    }
+
+    // Kprobes and system-wide uprobes are opened for any pid on cpu 0
+    let (pid, cpu) = if pid < 0 { (-1, 0) } else { (pid, -1) };
+    let pfd = unsafe {
+        libc::syscall(
+            libc::SYS_perf_event_open,
+            &attr as *const PerfEventAttr,
+            pid,
+            cpu,
+            -1,
+            PERF_FLAG_FD_CLOEXEC,
+        )
+    };
+    if pfd < 0 {
+        return Err(Error::System(errno::errno()));
+    }
+
+    Ok(pfd as i32)
+}
@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+
+use lazy_static::lazy_static;
+use num_enum::TryFromPrimitive;
+
+/// Level of a libbpf log message, from least to most verbose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[repr(u32)]
+pub enum PrintLevel {
+    Warn = libbpf_sys::LIBBPF_WARN,
+    Info = libbpf_sys::LIBBPF_INFO,
+    Debug = libbpf_sys::LIBBPF_DEBUG,
+}
+
+/// Callback receiving the level and formatted text of libbpf log messages.
+///
+/// Messages usually end with a newline.
+pub type PrintCallback = Box<dyn Fn(PrintLevel, String) + Send + Sync>;
+
+/// The installed [`PrintCallback`], shared so it can be called without holding `PRINT_CB`
+type SharedPrintCallback = Arc<dyn Fn(PrintLevel, String) + Send + Sync>;
+
+lazy_static! {
+    /// Starts out mirroring libbpf's default of printing warnings and info messages to stderr
+    static ref PRINT_CB: Mutex<Option<(SharedPrintCallback, PrintLevel)>> =
+        Mutex::new(Some((Arc::new(|_, msg| eprint!("{}", msg)), PrintLevel::Info)));
+}
+
+thread_local! {
+    /// Messages collected by `capture()` on this thread
+    static CAPTURED: RefCell<Option<Vec<(PrintLevel, String)>>> = const { RefCell::new(None) };
+}
+
+fn lock_print_cb() -> MutexGuard<'static, Option<(SharedPrintCallback, PrintLevel)>> {
+    // Ignore poisoning: the lock only guards replacing the callback, which can't be left half done
+    match PRINT_CB.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Installs `outer_print_cb` as the libbpf print function. It stays installed for the lifetime
+/// of the process, so `set_print()` and `capture()` on other threads never swap it out from
+/// under a running libbpf call.
+fn install_print_fn() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        libbpf_sys::libbpf_set_print(Some(outer_print_cb));
+    });
+}
+
+extern "C" fn outer_print_cb(
+    level: libbpf_sys::libbpf_print_level,
+    fmtstr: *const c_char,
+    va_list: *mut libbpf_sys::__va_list_tag,
+) -> i32 {
+    let level = match PrintLevel::try_from(level) {
+        Ok(level) => level,
+        Err(_) => return 0,
+    };
+
+    let capturing = CAPTURED.with(|captured| captured.borrow().is_some());
+    // The callback runs after the lock is released, so it may call `set_print()` itself
+    let print_cb = match &*lock_print_cb() {
+        Some((cb, min_level)) if level <= *min_level => Some(cb.clone()),
+        _ => None,
+    };
+    if !capturing && print_cb.is_none() {
+        return 0;
+    }
+
+    let msg = match unsafe { vsprintf::vsprintf(fmtstr, va_list) } {
+        Ok(msg) => msg,
+        Err(e) => {
+            eprintln!("Failed to parse libbpf output: {}", e);
+            return 1;
+        }
+    };
+
+    if capturing {
+        CAPTURED.with(|captured| {
+            if let Some(msgs) = captured.borrow_mut().as_mut() {
+                msgs.push((level, msg.clone()));
+            }
+        });
+    }
+
+    if let Some(cb) = print_cb {
+        cb(level, msg);
+    }
+
+    0
+}
+
+/// Set the process-wide callback for libbpf log messages.
+///
+/// Messages more verbose than `min_level` are dropped. Passing `None` as `callback` disables all
+/// libbpf output. Without a call to this function, libbpf prints warnings and info messages to
+/// stderr.
+///
+/// The callback is called without any lock held, so it may itself call `set_print()` or libbpf
+/// functions that print.
+///
+/// ```no_run
+/// use libbpf_rs::{set_print, PrintLevel};
+///
+/// set_print(
+///     Some(Box::new(|level, msg| eprint!("libbpf {:?}: {}", level, msg))),
+///     PrintLevel::Info,
+/// );
+/// ```
+pub fn set_print(callback: Option<PrintCallback>, min_level: PrintLevel) {
+    let callback = callback.map(|cb| (SharedPrintCallback::from(cb), min_level));
+    // Drop the previous callback outside of the lock, in case dropping it prints
+    let _prev = std::mem::replace(&mut *lock_print_cb(), callback);
+    install_print_fn();
+}
+
+/// Runs `f` and returns its result together with all messages libbpf printed on this thread
+/// while running it, independent of the callback set with [`set_print()`].
+pub(crate) fn capture<T, F: FnOnce() -> T>(f: F) -> (T, Vec<(PrintLevel, String)>) {
+    install_print_fn();
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(Vec::new()));
+
+    let ret = f();
+
+    let msgs = CAPTURED.with(|captured| captured.borrow_mut().take());
+
+    (ret, msgs.unwrap_or_default())
+}
+
+/// [`PrintCallback`] forwarding libbpf messages to the [`log`](https://crates.io/crates/log)
+/// crate with target `libbpf`.
+///
+/// ```no_run
+/// use libbpf_rs::{print_to_log, set_print, PrintLevel};
+///
+/// set_print(Some(Box::new(print_to_log)), PrintLevel::Debug);
+/// ```
+#[cfg(feature = "log")]
+pub fn print_to_log(level: PrintLevel, msg: String) {
+    let level = match level {
+        PrintLevel::Warn => log::Level::Warn,
+        PrintLevel::Info => log::Level::Info,
+        PrintLevel::Debug => log::Level::Debug,
+    };
+
+    log::log!(target: "libbpf", level, "{}", msg.trim_end());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ObjectBuilder;
+
+    #[test]
+    fn test_set_print() {
+        let msgs = Arc::new(Mutex::new(Vec::new()));
+        let msgs_cb = msgs.clone();
+        set_print(
+            Some(Box::new(move |level, msg| {
+                msgs_cb.lock().unwrap().push((level, msg))
+            })),
+            PrintLevel::Warn,
+        );
+
+        // libbpf warns about objects it fails to parse
+        assert!(ObjectBuilder::default()
+            .open_memory("invalid", &[0; 64])
+            .is_err());
+
+        let msgs = msgs.lock().unwrap();
+        assert!(!msgs.is_empty());
+        assert!(msgs.iter().all(|(level, _)| *level == PrintLevel::Warn));
+        drop(msgs);
+
+        // A callback replacing itself must not deadlock
+        let calls = Arc::new(Mutex::new(0));
+        let calls_cb = calls.clone();
+        set_print(
+            Some(Box::new(move |_, _| {
+                *calls_cb.lock().unwrap() += 1;
+                set_print(None, PrintLevel::Warn);
+            })),
+            PrintLevel::Warn,
+        );
+        assert!(ObjectBuilder::default()
+            .open_memory("invalid", &[0; 64])
+            .is_err());
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_capture() {
+        let (ret, msgs) = capture(|| ObjectBuilder::default().open_memory("invalid", &[0; 64]));
+        assert!(ret.is_err());
+        assert!(!msgs.is_empty());
+        assert!(msgs.iter().all(|(_, msg)| msg.starts_with("libbpf: ")));
+
+        // Nothing is captured outside of `capture()`
+        assert!(ObjectBuilder::default()
+            .open_memory("invalid", &[0; 64])
+            .is_err());
+        assert!(CAPTURED.with(|captured| captured.borrow().is_none()));
+    }
+
+    #[test]
+    fn test_capture_concurrent() {
+        let (ret, msgs) = capture(|| {
+            // A capture on another thread ending first must not end this one
+            std::thread::spawn(|| capture(|| ())).join().unwrap();
+            ObjectBuilder::default().open_memory("invalid", &[0; 64])
+        });
+        assert!(ret.is_err());
+        assert!(!msgs.is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+//! Probe the running kernel for supported BPF features
+//!
+//! Each probe tries to load a trivial program or create a small map, so the caller needs the
+//! privileges to do so (usually `CAP_SYS_ADMIN` or `CAP_BPF`). Without them, results are
+//! meaningless.
+//!
+//! For example, to check whether the kernel supports ring buffers:
+//! ```no_run
+//! use libbpf_rs::probe;
+//! use libbpf_rs::MapType;
+//!
+//! if probe::is_map_type_supported(MapType::RingBuf).unwrap() {
+//!     println!("ring buffers are supported");
+//! }
+//! ```
+
+use std::convert::TryFrom;
+
+use crate::*;
+
+/// Program types libbpf can only load when attached to a BTF type, so loading the generic probe
+/// program tells us nothing about kernel support.
+fn check_prog_type_probeable(prog_type: &ProgramType) -> Result<()> {
+    match prog_type {
+        ProgramType::Unspec
+        | ProgramType::Tracing
+        | ProgramType::StructOps
+        | ProgramType::Ext
+        | ProgramType::Lsm
+        | ProgramType::Unknown => Err(Error::InvalidInput(format!(
+            "Program type {} cannot be probed",
+            prog_type
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns whether the kernel supports loading programs of type `prog_type`.
+///
+/// Returns [`Error::InvalidInput`] for program types that require a BTF attach target
+/// (`Tracing`, `StructOps`, `Ext`, `Lsm`) as well as `Unspec` and `Unknown`.
+pub fn is_prog_type_supported(prog_type: ProgramType) -> Result<bool> {
+    check_prog_type_probeable(&prog_type)?;
+
+    Ok(unsafe { libbpf_sys::bpf_probe_prog_type(prog_type as u32, 0) })
+}
+
+/// Returns whether the kernel supports creating maps of type `map_type`.
+///
+/// Returns [`Error::InvalidInput`] for `StructOps` maps, which need a kernel BTF type to be
+/// created, as well as `Unspec` and `Unknown`.
+pub fn is_map_type_supported(map_type: MapType) -> Result<bool> {
+    match map_type {
+        MapType::Unspec | MapType::StructOps | MapType::Unknown => {
+            return Err(Error::InvalidInput(format!(
+                "Map type {} cannot be probed",
+                map_type
+            )))
+        }
+        _ => (),
+    }
+
+    Ok(unsafe { libbpf_sys::bpf_probe_map_type(map_type as u32, 0) })
+}
+
+/// Returns whether BPF helper `helper` may be called from programs of type `prog_type`.
+///
+/// `helper` is one of the `libbpf_sys::BPF_FUNC_*` constants, e.g.
+/// `libbpf_sys::BPF_FUNC_map_lookup_elem`. Returns `false` if `prog_type` itself is not
+/// supported.
+pub fn is_helper_supported(
+    prog_type: ProgramType,
+    helper: libbpf_sys::bpf_func_id,
+) -> Result<bool> {
+    if !is_prog_type_supported(prog_type.clone())? {
+        return Ok(false);
+    }
+
+    Ok(unsafe { libbpf_sys::bpf_probe_helper(helper, prog_type as u32, 0) })
+}
+
+/// Returns whether the kernel exposes its own BTF (i.e. was built with `CONFIG_DEBUG_INFO_BTF`),
+/// which is required for CO-RE relocations unless a custom BTF is provided.
+pub fn has_kernel_btf() -> bool {
+    let btf = unsafe { libbpf_sys::libbpf_find_kernel_btf() };
+    if unsafe { libbpf_sys::libbpf_get_error(btf as *const _) } != 0 {
+        return false;
+    }
+
+    unsafe { libbpf_sys::btf__free(btf) };
+    true
+}
+
+/// Returns every probeable [`ProgramType`] supported by the kernel.
+///
+/// Program types rejected by [`is_prog_type_supported()`] are never included.
+pub fn supported_prog_types() -> Vec<ProgramType> {
+    (0..)
+        .map_while(|ty| ProgramType::try_from(ty).ok())
+        .filter(|ty| matches!(is_prog_type_supported(ty.clone()), Ok(true)))
+        .collect()
+}
+
+/// Returns every probeable [`MapType`] supported by the kernel.
+///
+/// Map types rejected by [`is_map_type_supported()`] are never included.
+pub fn supported_map_types() -> Vec<MapType> {
+    (0..)
+        .map_while(|ty| MapType::try_from(ty).ok())
+        .filter(|ty| matches!(is_map_type_supported(ty.clone()), Ok(true)))
+        .collect()
+}
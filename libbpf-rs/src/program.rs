@@ -1,7 +1,10 @@
 use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fs;
 use std::path::Path;
+use std::time::Duration;
 
-use nix::errno;
+use nix::{errno, libc, unistd};
 use num_enum::TryFromPrimitive;
 use strum_macros::Display;
 
@@ -19,6 +22,43 @@ impl OpenProgram {
         OpenProgram { ptr }
     }
 
+    pub fn name(&self) -> Result<&str> {
+        // bpf_program__name never returns NULL
+        let name_ptr = unsafe { libbpf_sys::bpf_program__name(self.ptr) };
+        unsafe { CStr::from_ptr(name_ptr) }
+            .to_str()
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    /// Name of the section this `OpenProgram` belongs to.
+    pub fn section(&self) -> Result<&str> {
+        // bpf_program__section_name never returns NULL
+        let section_ptr = unsafe { libbpf_sys::bpf_program__section_name(self.ptr) };
+        unsafe { CStr::from_ptr(section_ptr) }
+            .to_str()
+            .map_err(|e| Error::Internal(e.to_string()))
+    }
+
+    /// Whether the program is loaded by [`OpenObject::load()`].
+    pub fn autoload(&self) -> bool {
+        unsafe { libbpf_sys::bpf_program__autoload(self.ptr) }
+    }
+
+    /// Set whether the program is loaded by [`OpenObject::load()`]. Programs are loaded by
+    /// default.
+    ///
+    /// A program that is not loaded is still part of the loaded [`Object`], but has no valid file
+    /// descriptor and cannot be attached.
+    pub fn set_autoload(&mut self, autoload: bool) -> Result<()> {
+        let ret = unsafe { libbpf_sys::bpf_program__set_autoload(self.ptr, autoload) };
+        if ret != 0 {
+            // Error code is returned negative, flip to positive to match errno
+            return Err(Error::System(-ret)).context("set autoload of", self.name().unwrap_or(""));
+        }
+
+        Ok(())
+    }
+
     pub fn set_prog_type(&mut self, prog_type: ProgramType) {
         unsafe {
             libbpf_sys::bpf_program__set_type(self.ptr, prog_type as u32);
@@ -114,22 +154,120 @@ pub enum ProgramAttachType {
     Unknown = u32::MAX,
 }
 
+/// Options for [`Program::attach_kprobe_with_opts()`].
+#[derive(Clone, Debug, Default)]
+pub struct KprobeOpts {
+    /// Attach to the function return instead of its entry
+    pub retprobe: bool,
+    /// Offset of the probed instruction from the start of the function. Must be `0` for
+    /// return probes.
+    pub offset: usize,
+    /// Value returned by `bpf_get_attach_cookie()` in the program. `0` means no cookie.
+    ///
+    /// Attaching with a cookie requires kernel 5.15 or newer.
+    pub cookie: u64,
+}
+
+/// Options for [`Program::attach_kprobes()`].
+#[derive(Clone, Debug, Default)]
+pub struct KprobesOpts {
+    /// Attach to the function returns instead of their entries
+    pub retprobe: bool,
+    /// One cookie per function, in the same order as the function names. May be empty if no
+    /// cookies are needed. See [`KprobeOpts::cookie`].
+    pub cookies: Vec<u64>,
+}
+
+/// Options for [`Program::attach_uprobe_with_opts()`].
+#[derive(Clone, Debug, Default)]
+pub struct UprobeOpts {
+    /// Attach to the function return instead of its entry
+    pub retprobe: bool,
+    /// See [`KprobeOpts::cookie`]
+    pub cookie: u64,
+}
+
+/// Output of [`Program::test_run()`].
+pub struct TestRunResult {
+    /// Value returned by the program on the last run
+    pub return_value: u32,
+    /// Packet data after the program ran, possibly modified by the program
+    pub data_out: Vec<u8>,
+    /// Context after the program ran. `None` if no context was passed in.
+    pub ctx_out: Option<Vec<u8>>,
+    /// Average duration of a single run as measured by the kernel
+    pub duration: Duration,
+}
+
 /// Represents a loaded [`Program`].
 ///
+/// A `Program` is either part of an [`Object`] or a standalone program that owns its file
+/// descriptor, e.g. one obtained with [`Program::from_pinned_path()`].
+///
 /// This struct is not safe to clone because the underlying libbpf resource cannot currently
 /// be protected from data races.
 ///
 /// If you attempt to attach a `Program` with the wrong attach method, the `attach_*`
 /// method will fail with the appropriate error.
 pub struct Program {
+    /// Null for standalone programs, which are not managed by libbpf
     pub(crate) ptr: *mut libbpf_sys::bpf_program,
+    /// Object the program belongs to, null for standalone programs
+    obj: *mut libbpf_sys::bpf_object,
+    fd: i32,
+    ty: libbpf_sys::bpf_prog_type,
     name: String,
     section: String,
 }
 
 impl Program {
-    pub(crate) fn new(ptr: *mut libbpf_sys::bpf_program, name: String, section: String) -> Self {
-        Program { ptr, name, section }
+    pub(crate) fn new(
+        ptr: *mut libbpf_sys::bpf_program,
+        obj: *mut libbpf_sys::bpf_object,
+        name: String,
+        section: String,
+    ) -> Self {
+        Program {
+            ptr,
+            obj,
+            fd: unsafe { libbpf_sys::bpf_program__fd(ptr) },
+            ty: unsafe { libbpf_sys::bpf_program__get_type(ptr) },
+            name,
+            section,
+        }
+    }
+
+    /// Get an owned handle to the program pinned at `path` in bpffs.
+    ///
+    /// The returned `Program` is not part of an [`Object`]: its [`Program::section()`] is empty,
+    /// its [`Program::attach_type()`] is unknown and the `attach_*` methods that rely on libbpf
+    /// fail with [`Error::InvalidInput`].
+    pub fn from_pinned_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path_c = util::path_to_cstring(&path)?;
+        let path_ptr = path_c.as_ptr();
+
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path_ptr) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno()))
+                .context("open pinned program", path.as_ref().display());
+        }
+
+        let info = match query::get_info_by_fd::<libbpf_sys::bpf_prog_info>(fd) {
+            Ok(info) => info,
+            Err(e) => {
+                let _ = unistd::close(fd);
+                return Err(e);
+            }
+        };
+
+        Ok(Program {
+            ptr: std::ptr::null_mut(),
+            obj: std::ptr::null_mut(),
+            fd,
+            ty: info.type_,
+            name: query::name_arr_to_string(&info.name, ""),
+            section: String::new(),
+        })
     }
 
     pub fn name(&self) -> &str {
@@ -142,7 +280,7 @@ impl Program {
     }
 
     pub fn prog_type(&self) -> ProgramType {
-        match ProgramType::try_from(unsafe { libbpf_sys::bpf_program__get_type(self.ptr) }) {
+        match ProgramType::try_from(self.ty) {
             Ok(ty) => ty,
             Err(_) => ProgramType::Unknown,
         }
@@ -150,10 +288,15 @@ impl Program {
 
     /// Returns a file descriptor to the underlying program.
     pub fn fd(&self) -> i32 {
-        unsafe { libbpf_sys::bpf_program__fd(self.ptr) }
+        self.fd
     }
 
     pub fn attach_type(&self) -> ProgramAttachType {
+        // The kernel does not report the expected attach type of loaded programs
+        if self.ptr.is_null() {
+            return ProgramAttachType::Unknown;
+        }
+
         match ProgramAttachType::try_from(unsafe {
             libbpf_sys::bpf_program__get_expected_attach_type(self.ptr)
         }) {
@@ -162,16 +305,34 @@ impl Program {
         }
     }
 
+    fn check_object_program(&self) -> Result<()> {
+        if self.ptr.is_null() {
+            Err(Error::InvalidInput(format!(
+                "Program {} is not part of an Object",
+                self.name
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
     /// [Pin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// this program to bpffs.
     pub fn pin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        let path_c = util::path_to_cstring(&path)?;
         let path_ptr = path_c.as_ptr();
 
-        let ret = unsafe { libbpf_sys::bpf_program__pin(self.ptr, path_ptr) };
+        let ret = if self.ptr.is_null() {
+            match unsafe { libbpf_sys::bpf_obj_pin(self.fd, path_ptr) } {
+                0 => 0,
+                _ => -errno::errno(),
+            }
+        } else {
+            unsafe { libbpf_sys::bpf_program__pin(self.ptr, path_ptr) }
+        };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("pin", path.as_ref().display())
         } else {
             Ok(())
         }
@@ -180,13 +341,19 @@ impl Program {
     /// [Unpin](https://facebookmicrosites.github.io/bpf/blog/2018/08/31/object-lifetime.html#bpffs)
     /// this program from bpffs
     pub fn unpin<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path_c = util::path_to_cstring(path)?;
+        if self.ptr.is_null() {
+            return fs::remove_file(&path)
+                .map_err(|e| Error::System(e.raw_os_error().unwrap_or(0)))
+                .context("unpin", path.as_ref().display());
+        }
+
+        let path_c = util::path_to_cstring(&path)?;
         let path_ptr = path_c.as_ptr();
 
         let ret = unsafe { libbpf_sys::bpf_program__unpin(self.ptr, path_ptr) };
         if ret != 0 {
             // Error code is returned negative, flip to positive to match errno
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("unpin", path.as_ref().display())
         } else {
             Ok(())
         }
@@ -194,10 +361,12 @@ impl Program {
 
     /// Auto-attach based on prog section
     pub fn attach(&mut self) -> Result<Link> {
+        self.check_object_program()?;
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach(self.ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -205,11 +374,15 @@ impl Program {
 
     /// Attach this program to a
     /// [cgroup](https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html).
+    ///
+    /// See [`cgroup`] to attach with flags or for kernels without cgroup link support.
     pub fn attach_cgroup(&mut self, cgroup_fd: i32) -> Result<Link> {
+        self.check_object_program()?;
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach_cgroup(self.ptr, cgroup_fd) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach cgroup", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -217,9 +390,27 @@ impl Program {
 
     /// Attach this program to a [perf event](https://linux.die.net/man/2/perf_event_open).
     pub fn attach_perf_event(&mut self, pfd: i32) -> Result<Link> {
+        self.check_object_program()?;
+
+        let ptr = unsafe { libbpf_sys::bpf_program__attach_perf_event(self.ptr, pfd) };
+        let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
+        if err != 0 {
+            Err(Error::System(err as i32)).context("attach perf event", &self.name)
+        } else {
+            Ok(Link::new(ptr))
+        }
+    }
+
+    /// Attach the perf event `pfd` opened for a kprobe or uprobe, taking ownership of it.
+    fn attach_probe_event(&mut self, pfd: i32, cookie: u64) -> Result<Link> {
+        if cookie != 0 {
+            return Link::new_perf_event(self.fd, pfd, cookie);
+        }
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach_perf_event(self.ptr, pfd) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
+            let _ = unistd::close(pfd);
             Err(Error::System(err as i32))
         } else {
             Ok(Link::new(ptr))
@@ -235,6 +426,8 @@ impl Program {
         binary_path: T,
         func_offset: usize,
     ) -> Result<Link> {
+        self.check_object_program()?;
+
         let path = util::path_to_cstring(binary_path.as_ref())?;
         let path_ptr = path.as_ptr();
         let ptr = unsafe {
@@ -248,30 +441,198 @@ impl Program {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach uprobe", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
     }
 
+    /// Attach this program to a [userspace
+    /// probe](https://www.kernel.org/doc/html/latest/trace/uprobetracer.html) on the function
+    /// `symbol` of `binary_path`.
+    ///
+    /// `symbol` is looked up in the `.symtab` and `.dynsym` sections of the binary. A bare
+    /// library name such as `libc.so.6` is resolved using `LD_LIBRARY_PATH` and
+    /// `/etc/ld.so.cache`. A `pid` of `-1` attaches to all processes.
+    pub fn attach_uprobe_symbol<T: AsRef<Path>>(
+        &mut self,
+        retprobe: bool,
+        pid: i32,
+        binary_path: T,
+        symbol: &str,
+    ) -> Result<Link> {
+        let (path, func_offset) = elf::resolve_binary_path(binary_path.as_ref())
+            .and_then(|path| elf::find_symbol_offset(&path, symbol).map(|off| (path, off)))
+            .context("attach uprobe", &self.name)?;
+
+        self.attach_uprobe(retprobe, pid, path, func_offset)
+    }
+
+    /// Attach this program to a [userspace
+    /// probe](https://www.kernel.org/doc/html/latest/trace/uprobetracer.html) at file offset
+    /// `func_offset` of `binary_path`, with additional options.
+    ///
+    /// A `pid` of `-1` attaches to all processes.
+    pub fn attach_uprobe_with_opts<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        func_offset: usize,
+        opts: &UprobeOpts,
+    ) -> Result<Link> {
+        self.check_object_program()?;
+
+        let path = util::path_to_cstring(binary_path.as_ref())?;
+        perf_event::open_probe(true, opts.retprobe, &path, func_offset as u64, 0, pid)
+            .and_then(|pfd| self.attach_probe_event(pfd, opts.cookie))
+            .context("attach uprobe", &self.name)
+    }
+
+    /// Attach this program to all locations of the [USDT
+    /// probe](https://sourceware.org/systemtap/wiki/UserSpaceProbeImplementation)
+    /// `provider:name` in `binary_path`.
+    ///
+    /// The program must be written with the `BPF_USDT()` macro from the `usdt.bpf.h` header
+    /// shipped with libbpf-cargo, which provides `bpf_usdt_arg()` to read probe arguments.
+    /// Probe semaphores are incremented while the program is attached. Bare library names are
+    /// resolved like in [`Program::attach_uprobe_symbol()`] and a `pid` of `-1` attaches to all
+    /// processes.
+    ///
+    /// Requires kernel 5.15 or newer for BPF cookie support.
+    pub fn attach_usdt<T: AsRef<Path>>(
+        &mut self,
+        pid: i32,
+        binary_path: T,
+        provider: &str,
+        name: &str,
+    ) -> Result<Link> {
+        self.check_object_program()?;
+        self.attach_usdt_impl(pid, binary_path.as_ref(), provider, name)
+            .context("attach usdt", &self.name)
+    }
+
+    fn attach_usdt_impl(
+        &mut self,
+        pid: i32,
+        binary_path: &Path,
+        provider: &str,
+        name: &str,
+    ) -> Result<Link> {
+        let specs_map_name = util::str_to_cstring(usdt::SPECS_MAP_NAME)?;
+        let specs_fd = unsafe {
+            libbpf_sys::bpf_object__find_map_fd_by_name(self.obj, specs_map_name.as_ptr())
+        };
+        if specs_fd < 0 {
+            return Err(Error::InvalidInput(format!(
+                "Map {} not found, is usdt.bpf.h included?",
+                usdt::SPECS_MAP_NAME
+            )));
+        }
+
+        let path = elf::resolve_binary_path(binary_path)?;
+        let targets = usdt::find_targets(&path, provider, name)?;
+        let (spec_ids, target_ids) = usdt::SpecIds::write_specs(specs_fd, &targets)?;
+
+        let path = util::path_to_cstring(&path)?;
+        let links = targets
+            .iter()
+            .zip(target_ids)
+            .map(|(target, id)| {
+                let pfd = perf_event::open_probe(
+                    true,
+                    false,
+                    &path,
+                    target.offset,
+                    target.sema_offset,
+                    pid,
+                )?;
+                Link::new_perf_event(self.fd, pfd, id as u64)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Link::new_usdt(links, spec_ids))
+    }
+
     /// Attach this program to a [kernel
     /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html).
     pub fn attach_kprobe<T: AsRef<str>>(&mut self, retprobe: bool, func_name: T) -> Result<Link> {
+        self.check_object_program()?;
+
         let func_name = util::str_to_cstring(func_name.as_ref())?;
         let func_name_ptr = func_name.as_ptr();
         let ptr =
             unsafe { libbpf_sys::bpf_program__attach_kprobe(self.ptr, retprobe, func_name_ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach kprobe", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
     }
 
+    /// Attach this program to a [kernel
+    /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html) with additional
+    /// options, e.g. at an offset into `func_name` or with a BPF cookie.
+    pub fn attach_kprobe_with_opts<T: AsRef<str>>(
+        &mut self,
+        func_name: T,
+        opts: &KprobeOpts,
+    ) -> Result<Link> {
+        self.check_object_program()?;
+
+        if opts.retprobe && opts.offset != 0 {
+            return Err(Error::InvalidInput(
+                "offset must be 0 for return probes".to_string(),
+            ));
+        }
+
+        let func_name = util::str_to_cstring(func_name.as_ref())?;
+        perf_event::open_probe(false, opts.retprobe, &func_name, opts.offset as u64, 0, -1)
+            .and_then(|pfd| self.attach_probe_event(pfd, opts.cookie))
+            .context("attach kprobe", &self.name)
+    }
+
+    /// Attach this program to a [kernel
+    /// probe](https://www.kernel.org/doc/html/latest/trace/kprobetrace.html) on each of
+    /// `func_names`, as with [`Program::attach_kprobe_with_opts()`].
+    ///
+    /// The returned [`Link`] owns all attachments and detaches them when dropped. Each function
+    /// still gets its own kprobe perf event, so this costs one file descriptor per function and
+    /// is not atomic. If attaching to any function fails, the attachments already created are
+    /// detached and the error is returned.
+    pub fn attach_kprobes<T: AsRef<str>>(
+        &mut self,
+        func_names: &[T],
+        opts: &KprobesOpts,
+    ) -> Result<Link> {
+        if !opts.cookies.is_empty() && opts.cookies.len() != func_names.len() {
+            return Err(Error::InvalidInput(format!(
+                "Got {} cookies for {} functions",
+                opts.cookies.len(),
+                func_names.len()
+            )));
+        }
+
+        func_names
+            .iter()
+            .enumerate()
+            .map(|(i, func_name)| {
+                let kprobe_opts = KprobeOpts {
+                    retprobe: opts.retprobe,
+                    offset: 0,
+                    cookie: opts.cookies.get(i).copied().unwrap_or(0),
+                };
+                self.attach_kprobe_with_opts(func_name, &kprobe_opts)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Link::new_multi)
+    }
+
     /// Attach this program to a [kernel
     /// tracepoint](https://www.kernel.org/doc/html/latest/trace/tracepoints.html).
     pub fn attach_tracepoint<T: AsRef<str>>(&mut self, tp_category: T, tp_name: T) -> Result<Link> {
+        self.check_object_program()?;
+
         let tp_category = util::str_to_cstring(tp_category.as_ref())?;
         let tp_category_ptr = tp_category.as_ptr();
         let tp_name = util::str_to_cstring(tp_name.as_ref())?;
@@ -281,7 +642,7 @@ impl Program {
         };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach tracepoint", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -290,12 +651,14 @@ impl Program {
     /// Attach this program to a [raw kernel
     /// tracepoint](https://lwn.net/Articles/748352/).
     pub fn attach_raw_tracepoint<T: AsRef<str>>(&mut self, tp_name: T) -> Result<Link> {
+        self.check_object_program()?;
+
         let tp_name = util::str_to_cstring(tp_name.as_ref())?;
         let tp_name_ptr = tp_name.as_ptr();
         let ptr = unsafe { libbpf_sys::bpf_program__attach_raw_tracepoint(self.ptr, tp_name_ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach raw tracepoint", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -303,10 +666,12 @@ impl Program {
 
     /// Attach to an [LSM](https://en.wikipedia.org/wiki/Linux_Security_Modules) hook
     pub fn attach_lsm(&mut self) -> Result<Link> {
+        self.check_object_program()?;
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach_lsm(self.ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach lsm", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -314,10 +679,12 @@ impl Program {
 
     /// Attach to a [fentry/fexit kernel probe](https://lwn.net/Articles/801479/)
     pub fn attach_trace(&mut self) -> Result<Link> {
+        self.check_object_program()?;
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach_trace(self.ptr) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach trace", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
@@ -328,20 +695,105 @@ impl Program {
         let err =
             unsafe { libbpf_sys::bpf_prog_attach(self.fd(), map_fd, self.attach_type() as u32, 0) };
         if err != 0 {
-            Err(Error::System(errno::errno()))
+            Err(Error::System(errno::errno())).context("attach sockmap", &self.name)
         } else {
             Ok(())
         }
     }
 
     /// Attach this program to [XDP](https://lwn.net/Articles/825998/)
+    ///
+    /// See [`Xdp`] to select the attach mode or for kernels without XDP link support.
     pub fn attach_xdp(&mut self, ifindex: i32) -> Result<Link> {
+        self.check_object_program()?;
+
         let ptr = unsafe { libbpf_sys::bpf_program__attach_xdp(self.ptr, ifindex) };
         let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
         if err != 0 {
-            Err(Error::System(err as i32))
+            Err(Error::System(err as i32)).context("attach xdp", &self.name)
         } else {
             Ok(Link::new(ptr))
         }
     }
+
+    /// Run this program in the kernel against `input` without attaching it, using
+    /// `BPF_PROG_TEST_RUN`.
+    ///
+    /// `ctx` is passed as the program context (e.g. a `struct __sk_buff` or `struct xdp_md`)
+    /// for program types that support it. The program is run `repeat` times; `0` is treated as
+    /// `1` by the kernel. Only some program types, such as XDP, TC and socket filters, can be
+    /// test run.
+    pub fn test_run(
+        &mut self,
+        input: &[u8],
+        ctx: Option<&[u8]>,
+        repeat: u32,
+    ) -> Result<TestRunResult> {
+        let repeat = i32::try_from(repeat).map_err(|_| {
+            Error::InvalidInput(format!("repeat {} is larger than {}", repeat, i32::MAX))
+        })?;
+        let size = |buf: &[u8], what: &str| {
+            u32::try_from(buf.len()).map_err(|_| {
+                Error::InvalidInput(format!("{} of {} bytes is too large", what, buf.len()))
+            })
+        };
+        let data_size_in = size(input, "input")?;
+        let ctx_size = ctx.map(|ctx| size(ctx, "ctx")).transpose()?;
+
+        // Programs may grow the packet, e.g. with `bpf_xdp_adjust_head()`. If the buffer is still
+        // too small the kernel fails with ENOSPC and reports the size it needs.
+        let mut data_out = vec![0u8; data_size_in.saturating_add(256) as usize];
+        let mut ctx_out = ctx.map(|c| vec![0u8; c.len()]);
+
+        loop {
+            let mut attr = libbpf_sys::bpf_prog_test_run_attr {
+                prog_fd: self.fd,
+                repeat,
+                data_in: input.as_ptr() as *const _,
+                data_size_in,
+                data_out: data_out.as_mut_ptr() as *mut _,
+                // Only ever sized from a u32
+                data_size_out: data_out.len() as u32,
+                ..Default::default()
+            };
+            if let (Some(ctx), Some(ctx_size), Some(ctx_out)) = (ctx, ctx_size, ctx_out.as_mut()) {
+                attr.ctx_in = ctx.as_ptr() as *const _;
+                attr.ctx_size_in = ctx_size;
+                attr.ctx_out = ctx_out.as_mut_ptr() as *mut _;
+                attr.ctx_size_out = ctx_size;
+            }
+
+            let ret = unsafe { libbpf_sys::bpf_prog_test_run_xattr(&mut attr) };
+            if ret != 0 {
+                let errno = errno::errno();
+                if errno == libc::ENOSPC && attr.data_size_out as usize > data_out.len() {
+                    data_out.resize(attr.data_size_out as usize, 0);
+                    continue;
+                }
+
+                return Err(Error::System(errno)).context("test run", &self.name);
+            }
+
+            data_out.truncate(attr.data_size_out as usize);
+            if let Some(ctx_out) = ctx_out.as_mut() {
+                ctx_out.truncate(attr.ctx_size_out as usize);
+            }
+
+            return Ok(TestRunResult {
+                return_value: attr.retval,
+                data_out,
+                ctx_out,
+                duration: Duration::from_nanos(attr.duration as u64),
+            });
+        }
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        // Programs that are part of an object are closed along with the object
+        if self.ptr.is_null() {
+            let _ = unistd::close(self.fd);
+        }
+    }
 }
@@ -85,7 +85,24 @@ macro_rules! gen_info_impl {
     };
 }
 
-fn name_arr_to_string(a: &[c_char], default: &str) -> String {
+/// Queries kernel information about the BPF object referred to by `fd`.
+///
+/// `T` must be one of the `bpf_*_info` uapi structs matching the type of object.
+pub(crate) fn get_info_by_fd<T>(fd: i32) -> Result<T> {
+    // See the comment in `gen_info_impl!` on why we need zeroed memory
+    let mut item: T = unsafe { std::mem::zeroed() };
+    let item_ptr: *mut T = &mut item;
+    let mut len = size_of::<T>() as u32;
+
+    let ret = unsafe { libbpf_sys::bpf_obj_get_info_by_fd(fd, item_ptr as *mut c_void, &mut len) };
+    if ret != 0 {
+        return Err(Error::System(errno::errno())).context("get info of fd", fd);
+    }
+
+    Ok(item)
+}
+
+pub(crate) fn name_arr_to_string(a: &[c_char], default: &str) -> String {
     let converted_arr: Vec<u8> = a
         .iter()
         .take_while(|x| **x != 0)
@@ -138,6 +155,21 @@ pub struct ProgramInfo {
 }
 
 impl ProgramInfo {
+    /// Queries kernel information about the program with id `id`.
+    pub(crate) fn from_id(id: u32) -> Result<Self> {
+        let fd = unsafe { libbpf_sys::bpf_prog_get_fd_by_id(id) };
+        if fd < 0 {
+            return Err(Error::System(errno::errno())).context("get fd of program id", id);
+        }
+
+        let info = get_info_by_fd::<libbpf_sys::bpf_prog_info>(fd).and_then(|info| {
+            Self::from_uapi(fd, info)
+                .ok_or_else(|| Error::Internal(format!("Failed to parse info of program {}", id)))
+        });
+        let _ = close(fd);
+        info
+    }
+
     fn from_uapi(_fd: i32, s: libbpf_sys::bpf_prog_info) -> Option<Self> {
         let name = name_arr_to_string(&s.name, "(?)");
         let ty = match ProgramType::try_from(s.type_) {
@@ -238,6 +270,14 @@ impl MapInfo {
     }
 }
 
+impl MapInfo {
+    pub(crate) fn from_fd(fd: i32) -> Result<Self> {
+        let info = get_info_by_fd::<libbpf_sys::bpf_map_info>(fd)?;
+        Self::from_uapi(fd, info)
+            .ok_or_else(|| Error::Internal("Failed to parse map info".to_string()))
+    }
+}
+
 gen_info_impl!(
     /// Iterator that returns [`MapInfo`]s.
     MapInfoIter,
@@ -79,7 +79,8 @@ impl RingBufferBuilder {
                 // Handle errors
                 let err = unsafe { libbpf_sys::libbpf_get_error(ptr as *const _) };
                 if err != 0 {
-                    return Err(Error::System(err as i32));
+                    return Err(Error::System(err as i32))
+                        .context("create ring buffer for map fd", fd);
                 }
             } else {
                 // Add a ringbuf to the existing ringbuf manager
@@ -89,7 +90,8 @@ impl RingBufferBuilder {
 
                 // Handle errors
                 if err != 0 {
-                    return Err(Error::System(err as i32));
+                    return Err(Error::System(err as i32))
+                        .context("add ring buffer for map fd", fd);
                 }
             }
 
@@ -134,7 +136,7 @@ impl RingBuffer {
         let ret = unsafe { libbpf_sys::ring_buffer__poll(self.ptr, timeout.as_millis() as i32) };
 
         if ret < 0 {
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("poll", "ring buffer")
         } else {
             Ok(())
         }
@@ -149,7 +151,7 @@ impl RingBuffer {
         let ret = unsafe { libbpf_sys::ring_buffer__consume(self.ptr) };
 
         if ret < 0 {
-            Err(Error::System(-ret))
+            Err(Error::System(-ret)).context("consume", "ring buffer")
         } else {
             Ok(())
         }
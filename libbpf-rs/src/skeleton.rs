@@ -6,6 +6,9 @@ use std::mem::size_of;
 use std::os::raw::{c_char, c_ulong};
 use std::ptr;
 
+use nix::sys::mman::{mmap, munmap, MapFlags as MmapFlags, ProtFlags};
+use nix::unistd::{sysconf, SysconfVar};
+
 use libbpf_sys::{
     bpf_link, bpf_map, bpf_map_skeleton, bpf_object, bpf_object_skeleton, bpf_prog_skeleton,
     bpf_program,
@@ -190,6 +193,7 @@ impl<'a> ObjectSkeletonConfigBuilder<'a> {
             progs: self.progs,
             maps_layout,
             progs_layout,
+            owned_mmaps: Vec::new(),
             _data: self.data,
             _string_pool: string_pool,
         })
@@ -212,6 +216,8 @@ pub struct ObjectSkeletonConfig<'a> {
     maps_layout: Option<Layout>,
     /// Same as above
     progs_layout: Option<Layout>,
+    /// Map memory mapped by `mmap_maps()` that libbpf does not know about, as (addr, size)
+    owned_mmaps: Vec<(*mut c_void, usize)>,
     /// Hold this reference so that compiler guarantees buffer lives as long as us
     _data: &'a [u8],
     /// Hold strings alive so pointers to them stay valid
@@ -258,6 +264,61 @@ impl<'a> ObjectSkeletonConfig<'a> {
 
         Ok(*self.progs[index].link)
     }
+
+    /// Memory map the mmap'able maps of the loaded object, as `bpf_object__load_skeleton()` does
+    /// after loading the object. Only needed if the object was not loaded through
+    /// `bpf_object__load_skeleton()`.
+    pub(crate) fn mmap_maps(&mut self) -> Result<()> {
+        let page_size = match sysconf(SysconfVar::PAGE_SIZE) {
+            Ok(Some(size)) => size as usize,
+            _ => return Err(Error::Internal("Failed to get page size".to_string())),
+        };
+
+        for map in self.maps.iter_mut() {
+            let mmaped = match map.mmaped {
+                Some(ref mut mmaped) => mmaped,
+                None => continue,
+            };
+
+            let map_ptr = *map.p;
+            let map_flags = unsafe { libbpf_sys::bpf_map__map_flags(map_ptr) };
+            if map_flags & libbpf_sys::BPF_F_MMAPABLE == 0 {
+                **mmaped = ptr::null_mut();
+                continue;
+            }
+
+            let prot = if map_flags & libbpf_sys::BPF_F_RDONLY_PROG != 0 {
+                ProtFlags::PROT_READ
+            } else {
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE
+            };
+
+            let value_size = unsafe { libbpf_sys::bpf_map__value_size(map_ptr) } as usize;
+            let max_entries = unsafe { libbpf_sys::bpf_map__max_entries(map_ptr) } as usize;
+            let size = (((value_size + 7) & !7) * max_entries + page_size - 1) & !(page_size - 1);
+
+            // Like libbpf, replace the memory holding the map's initial value with the map itself
+            // at the same address. Maps without initial value (i.e. .kconfig) get a new mapping
+            // that we have to clean up ourselves.
+            let addr = **mmaped;
+            let flags = if addr.is_null() {
+                MmapFlags::MAP_SHARED
+            } else {
+                MmapFlags::MAP_SHARED | MmapFlags::MAP_FIXED
+            };
+            let fd = unsafe { libbpf_sys::bpf_map__fd(map_ptr) };
+
+            let new_addr = unsafe { mmap(addr, size, prot, flags, fd, 0) }
+                .map_err(|e| Error::System(e.as_errno().map_or(0, |e| e as i32)))
+                .context("mmap map fd", fd)?;
+            if addr.is_null() {
+                self.owned_mmaps.push((new_addr, size));
+            }
+            **mmaped = new_addr;
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> Drop for ObjectSkeletonConfig<'a> {
@@ -287,5 +348,9 @@ impl<'a> Drop for ObjectSkeletonConfig<'a> {
                 dealloc(self.inner.progs as _, layout);
             }
         }
+
+        for (addr, size) in self.owned_mmaps.drain(..) {
+            let _ = unsafe { munmap(addr, size) };
+        }
     }
 }
@@ -0,0 +1,272 @@
+use std::fmt;
+use std::mem::{size_of, MaybeUninit};
+use std::ptr;
+
+use nix::libc;
+
+use crate::*;
+
+/// Direction of traffic a TC filter is attached to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcAttachPoint {
+    Ingress,
+    Egress,
+}
+
+impl TcAttachPoint {
+    fn to_libbpf(self) -> libbpf_sys::bpf_tc_attach_point {
+        match self {
+            TcAttachPoint::Ingress => libbpf_sys::BPF_TC_INGRESS,
+            TcAttachPoint::Egress => libbpf_sys::BPF_TC_EGRESS,
+        }
+    }
+}
+
+impl fmt::Display for TcAttachPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcAttachPoint::Ingress => write!(f, "ingress"),
+            TcAttachPoint::Egress => write!(f, "egress"),
+        }
+    }
+}
+
+/// Options for [`TcHook::attach()`].
+#[derive(Clone, Debug, Default)]
+pub struct TcOpts {
+    /// Filter handle, or `0` to let the kernel pick one
+    pub handle: u32,
+    /// Filter priority, or `0` to let the kernel pick one
+    pub priority: u32,
+}
+
+// libbpf rejects options with non-zero bytes past the fields it knows, including trailing
+// padding. Padding is not preserved when a struct is moved, even one from `mem::zeroed()`, so
+// the structs are zeroed in place in storage owned by the caller and only written field by
+// field.
+fn tc_hook(
+    storage: &mut MaybeUninit<libbpf_sys::bpf_tc_hook>,
+    ifindex: i32,
+    attach_point: libbpf_sys::bpf_tc_attach_point,
+) -> &mut libbpf_sys::bpf_tc_hook {
+    let hook = unsafe {
+        ptr::write_bytes(storage.as_mut_ptr(), 0, 1);
+        &mut *storage.as_mut_ptr()
+    };
+    hook.sz = size_of::<libbpf_sys::bpf_tc_hook>() as libbpf_sys::size_t;
+    hook.ifindex = ifindex;
+    hook.attach_point = attach_point;
+    hook
+}
+
+fn tc_opts(
+    storage: &mut MaybeUninit<libbpf_sys::bpf_tc_opts>,
+    handle: u32,
+    priority: u32,
+) -> &mut libbpf_sys::bpf_tc_opts {
+    let opts = unsafe {
+        ptr::write_bytes(storage.as_mut_ptr(), 0, 1);
+        &mut *storage.as_mut_ptr()
+    };
+    opts.sz = size_of::<libbpf_sys::bpf_tc_opts>() as libbpf_sys::size_t;
+    opts.handle = handle;
+    opts.priority = priority;
+    opts
+}
+
+/// The `clsact` qdisc of a network interface, which holds the ingress and egress TC filters
+/// [`ProgramType::SchedCls`] programs are attached as.
+///
+/// Creating and destroying the qdisc as well as managing filters is done over netlink and
+/// requires `CAP_NET_ADMIN`.
+#[derive(Clone, Debug)]
+pub struct TcHook {
+    ifindex: i32,
+}
+
+impl TcHook {
+    /// The `clsact` qdisc of the interface with index `ifindex`. Nothing is created until
+    /// [`TcHook::create()`] is called.
+    pub fn new(ifindex: i32) -> Self {
+        TcHook { ifindex }
+    }
+
+    pub fn ifindex(&self) -> i32 {
+        self.ifindex
+    }
+
+    /// Create the `clsact` qdisc. Does nothing if it already exists.
+    pub fn create(&self) -> Result<()> {
+        let mut storage = MaybeUninit::uninit();
+        let hook = tc_hook(
+            &mut storage,
+            self.ifindex,
+            libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS,
+        );
+        let ret = unsafe { libbpf_sys::bpf_tc_hook_create(hook) };
+        if ret != 0 && ret != -libc::EEXIST {
+            return Err(Error::System(-ret))
+                .context("create clsact qdisc on ifindex", self.ifindex);
+        }
+
+        Ok(())
+    }
+
+    /// Destroy the `clsact` qdisc along with all filters attached to it, including ones not
+    /// created by this process.
+    pub fn destroy(&self) -> Result<()> {
+        let mut storage = MaybeUninit::uninit();
+        let hook = tc_hook(
+            &mut storage,
+            self.ifindex,
+            libbpf_sys::BPF_TC_INGRESS | libbpf_sys::BPF_TC_EGRESS,
+        );
+        let ret = unsafe { libbpf_sys::bpf_tc_hook_destroy(hook) };
+        if ret != 0 {
+            return Err(Error::System(-ret))
+                .context("destroy clsact qdisc on ifindex", self.ifindex);
+        }
+
+        Ok(())
+    }
+
+    /// Attach `prog` as a filter at `attach_point`. The qdisc must have been created.
+    ///
+    /// Fails if a filter with the same handle and priority exists, use [`TcFilter::replace()`]
+    /// to swap the program of a filter. The returned [`TcFilter`] detaches the filter when
+    /// dropped.
+    pub fn attach(
+        &self,
+        prog: &Program,
+        attach_point: TcAttachPoint,
+        opts: &TcOpts,
+    ) -> Result<TcFilter> {
+        let (mut hook_storage, mut opts_storage) = (MaybeUninit::uninit(), MaybeUninit::uninit());
+        let hook = tc_hook(&mut hook_storage, self.ifindex, attach_point.to_libbpf());
+        let tc_opts = tc_opts(&mut opts_storage, opts.handle, opts.priority);
+        tc_opts.prog_fd = prog.fd();
+
+        let ret = unsafe { libbpf_sys::bpf_tc_attach(hook, tc_opts) };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context("attach tc filter", prog.name());
+        }
+
+        Ok(TcFilter {
+            ifindex: self.ifindex,
+            attach_point,
+            handle: tc_opts.handle,
+            priority: tc_opts.priority,
+            prog_id: tc_opts.prog_id,
+            disconnected: false,
+        })
+    }
+
+    /// Returns the id of the program attached as the filter with `handle` and `priority` at
+    /// `attach_point`. Both must be non-zero.
+    pub fn query(&self, attach_point: TcAttachPoint, handle: u32, priority: u32) -> Result<u32> {
+        let (mut hook_storage, mut opts_storage) = (MaybeUninit::uninit(), MaybeUninit::uninit());
+        let hook = tc_hook(&mut hook_storage, self.ifindex, attach_point.to_libbpf());
+        let opts = tc_opts(&mut opts_storage, handle, priority);
+        let ret = unsafe { libbpf_sys::bpf_tc_query(hook, opts) };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context("query tc filter on", attach_point);
+        }
+
+        Ok(opts.prog_id)
+    }
+
+    /// Detach the filter with `handle` and `priority` at `attach_point`, e.g. one left behind by
+    /// [`TcFilter::disconnect()`]. Both must be non-zero.
+    pub fn detach(&self, attach_point: TcAttachPoint, handle: u32, priority: u32) -> Result<()> {
+        let (mut hook_storage, mut opts_storage) = (MaybeUninit::uninit(), MaybeUninit::uninit());
+        let hook = tc_hook(&mut hook_storage, self.ifindex, attach_point.to_libbpf());
+        let opts = tc_opts(&mut opts_storage, handle, priority);
+        let ret = unsafe { libbpf_sys::bpf_tc_detach(hook, opts) };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context("detach tc filter on", attach_point);
+        }
+
+        Ok(())
+    }
+}
+
+/// A TC filter created by [`TcHook::attach()`].
+///
+/// The filter is detached when this object is dropped, unless it was disconnected with
+/// [`TcFilter::disconnect()`].
+#[derive(Debug)]
+pub struct TcFilter {
+    ifindex: i32,
+    attach_point: TcAttachPoint,
+    handle: u32,
+    priority: u32,
+    prog_id: u32,
+    disconnected: bool,
+}
+
+impl TcFilter {
+    pub fn ifindex(&self) -> i32 {
+        self.ifindex
+    }
+
+    pub fn attach_point(&self) -> TcAttachPoint {
+        self.attach_point
+    }
+
+    /// Filter handle, as picked by the kernel if none was requested.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Filter priority, as picked by the kernel if none was requested.
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    /// Id of the attached program.
+    pub fn prog_id(&self) -> u32 {
+        self.prog_id
+    }
+
+    /// Atomically replace the program of this filter with `prog`, keeping its handle and
+    /// priority. On failure the filter is left unchanged.
+    pub fn replace(&mut self, prog: &Program) -> Result<()> {
+        let (mut hook_storage, mut opts_storage) = (MaybeUninit::uninit(), MaybeUninit::uninit());
+        let hook = tc_hook(
+            &mut hook_storage,
+            self.ifindex,
+            self.attach_point.to_libbpf(),
+        );
+        let opts = tc_opts(&mut opts_storage, self.handle, self.priority);
+        opts.prog_fd = prog.fd();
+        opts.flags = libbpf_sys::BPF_TC_F_REPLACE;
+
+        let ret = unsafe { libbpf_sys::bpf_tc_attach(hook, opts) };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context("replace tc filter with", prog.name());
+        }
+
+        self.prog_id = opts.prog_id;
+        Ok(())
+    }
+
+    /// Leave the filter attached when this object is dropped. It can still be detached with
+    /// [`TcHook::detach()`] or by destroying the qdisc.
+    pub fn disconnect(&mut self) {
+        self.disconnected = true;
+    }
+
+    /// Detach the filter now, reporting errors which dropping the filter would ignore.
+    pub fn detach(mut self) -> Result<()> {
+        self.disconnected = true;
+        TcHook::new(self.ifindex).detach(self.attach_point, self.handle, self.priority)
+    }
+}
+
+impl Drop for TcFilter {
+    fn drop(&mut self) {
+        if !self.disconnected {
+            let _ = TcHook::new(self.ifindex).detach(self.attach_point, self.handle, self.priority);
+        }
+    }
+}
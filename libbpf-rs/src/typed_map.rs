@@ -0,0 +1,146 @@
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ptr;
+use std::slice;
+
+use crate::*;
+
+/// Marker trait for plain-old-data types that can be safely converted to and from the raw bytes
+/// stored in a [`Map`].
+///
+/// # Safety
+///
+/// Implementors must guarantee that:
+///
+/// * the type has no padding bytes (every byte of a value is initialized)
+/// * every possible bit pattern is a valid value of the type
+/// * the type has a stable memory layout (e.g. `#[repr(C)]`) matching the BPF side
+///
+/// In practice this means `#[repr(C)]` structs made only of other `Pod` types and laid out such
+/// that the compiler does not need to insert padding.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+fn pod_as_bytes<T: Pod>(val: &T) -> &[u8] {
+    // `Pod` guarantees there are no uninitialized (padding) bytes
+    unsafe { slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) }
+}
+
+fn pod_from_bytes<T: Pod>(bytes: &[u8]) -> T {
+    assert_eq!(bytes.len(), size_of::<T>());
+
+    // `Pod` guarantees any bit pattern is valid. The buffer may not be suitably aligned for `T`.
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// A [`Map`] with a statically known key and value type.
+///
+/// Construction validates [`Map::key_size()`] and [`Map::value_size()`] against the sizes of `K`
+/// and `V`, so all further operations can convert between Rust values and raw bytes without
+/// additional checks.
+pub struct TypedMap<'a, K: Pod, V: Pod> {
+    map: &'a mut Map,
+    _types: PhantomData<(K, V)>,
+}
+
+impl<'a, K: Pod, V: Pod> TypedMap<'a, K, V> {
+    /// Wrap `map`, checking that its key and value sizes match `K` and `V`.
+    ///
+    /// Returns [`Error::InvalidInput`] on a size mismatch.
+    pub fn new(map: &'a mut Map) -> Result<Self> {
+        if map.key_size() as usize != size_of::<K>() {
+            return Err(Error::InvalidInput(format!(
+                "map key_size {} != key type size {}",
+                map.key_size(),
+                size_of::<K>()
+            )));
+        }
+
+        if map.value_size() as usize != size_of::<V>() {
+            return Err(Error::InvalidInput(format!(
+                "map value_size {} != value type size {}",
+                map.value_size(),
+                size_of::<V>()
+            )));
+        }
+
+        Ok(Self {
+            map,
+            _types: PhantomData,
+        })
+    }
+
+    /// Returns a reference to the underlying untyped [`Map`].
+    pub fn map(&self) -> &Map {
+        self.map
+    }
+
+    /// Returns the value associated with `key`, if any.
+    pub fn lookup(&self, key: &K, flags: MapFlags) -> Result<Option<V>> {
+        Ok(self
+            .map
+            .lookup(pod_as_bytes(key), flags)?
+            .map(|v| pod_from_bytes(&v)))
+    }
+
+    /// Deletes the element associated with `key` from the map.
+    pub fn delete(&mut self, key: &K) -> Result<()> {
+        self.map.delete(pod_as_bytes(key))
+    }
+
+    /// Update the element associated with `key`.
+    pub fn update(&mut self, key: &K, value: &V, flags: MapFlags) -> Result<()> {
+        self.map
+            .update(pod_as_bytes(key), pod_as_bytes(value), flags)
+    }
+
+    /// Returns an iterator over keys in this map.
+    ///
+    /// The same stability caveats as [`Map::keys()`] apply.
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        self.map.keys().map(|k| pod_from_bytes(&k))
+    }
+
+    /// Returns an iterator over `(key, value)` pairs in this map.
+    ///
+    /// See [`Map::iter()`] for the semantics of iterating over a map that is being modified.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V)>> + '_ {
+        self.map
+            .iter()
+            .map(|entry| entry.map(|(k, v)| (pod_from_bytes(&k), pod_from_bytes(&v))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pod_roundtrip() {
+        let val: u64 = 0x0102_0304_0506_0708;
+        let bytes = pod_as_bytes(&val);
+        assert_eq!(bytes, &val.to_ne_bytes());
+        assert_eq!(pod_from_bytes::<u64>(bytes), val);
+
+        let arr: [u16; 3] = [1, 2, 3];
+        assert_eq!(pod_from_bytes::<[u16; 3]>(pod_as_bytes(&arr)), arr);
+    }
+
+    #[test]
+    fn test_pod_from_unaligned_bytes() {
+        let buf = [0u8, 1, 0, 0, 0];
+        assert_eq!(
+            pod_from_bytes::<u32>(&buf[1..]),
+            u32::from_ne_bytes([1, 0, 0, 0])
+        );
+    }
+}
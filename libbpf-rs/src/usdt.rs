@@ -0,0 +1,464 @@
+//! Userspace side of USDT support.
+//!
+//! Each USDT probe location is attached as a uprobe whose BPF cookie is the index of a
+//! `struct __bpf_usdt_spec` in the `__bpf_usdt_specs` map. The spec describes where to find each
+//! probe argument so `bpf_usdt_arg()` from `usdt.bpf.h` (shipped with libbpf-cargo) can fetch it.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use goblin::elf::Elf;
+use lazy_static::lazy_static;
+
+use crate::*;
+
+/// Name of the spec map defined by `usdt.bpf.h`
+pub(crate) const SPECS_MAP_NAME: &str = "__bpf_usdt_specs";
+/// `BPF_USDT_MAX_ARG_CNT` in `usdt.bpf.h`
+const MAX_ARG_CNT: usize = 12;
+/// Size of `struct __bpf_usdt_arg_spec`
+const ARG_SPEC_SIZE: usize = 16;
+/// Size of `struct __bpf_usdt_spec`
+pub(crate) const SPEC_SIZE: usize = 208;
+
+const NT_STAPSDT: u32 = 3;
+
+/// `enum __bpf_usdt_arg_type` in `usdt.bpf.h`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u32)]
+enum ArgType {
+    Const = 0,
+    Reg = 1,
+    RegDeref = 2,
+}
+
+/// Mirrors `struct __bpf_usdt_arg_spec` in `usdt.bpf.h`
+#[derive(Clone, Debug, PartialEq)]
+struct ArgSpec {
+    /// Constant value for `Const`, offset from the register value for `RegDeref`
+    val_off: u64,
+    arg_type: ArgType,
+    /// Offset of the register in `struct pt_regs`
+    reg_off: i16,
+    arg_signed: bool,
+    /// Shift used to sign or zero extend arguments smaller than 8 bytes
+    arg_bitshift: i8,
+}
+
+impl ArgSpec {
+    fn new(size: &str, arg_type: ArgType, val_off: u64, reg_off: i16) -> Result<Self> {
+        let size: i32 = size
+            .parse()
+            .map_err(|_| Error::InvalidInput(format!("Invalid USDT argument size {}", size)))?;
+        match size.abs() {
+            1 | 2 | 4 | 8 => Ok(Self {
+                val_off,
+                arg_type,
+                reg_off,
+                arg_signed: size < 0,
+                arg_bitshift: (64 - size.abs() * 8) as i8,
+            }),
+            _ => Err(Error::InvalidInput(format!(
+                "Invalid USDT argument size {}",
+                size
+            ))),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn reg_off(reg: &str) -> Option<i16> {
+    // Offsets in `struct pt_regs`, with all names that refer to (parts of) each register
+    const REGS: &[(i16, &[&str])] = &[
+        (0, &["r15", "r15d", "r15w", "r15b"]),
+        (8, &["r14", "r14d", "r14w", "r14b"]),
+        (16, &["r13", "r13d", "r13w", "r13b"]),
+        (24, &["r12", "r12d", "r12w", "r12b"]),
+        (32, &["rbp", "ebp", "bp", "bpl"]),
+        (40, &["rbx", "ebx", "bx", "bl"]),
+        (48, &["r11", "r11d", "r11w", "r11b"]),
+        (56, &["r10", "r10d", "r10w", "r10b"]),
+        (64, &["r9", "r9d", "r9w", "r9b"]),
+        (72, &["r8", "r8d", "r8w", "r8b"]),
+        (80, &["rax", "eax", "ax", "al"]),
+        (88, &["rcx", "ecx", "cx", "cl"]),
+        (96, &["rdx", "edx", "dx", "dl"]),
+        (104, &["rsi", "esi", "si", "sil"]),
+        (112, &["rdi", "edi", "di", "dil"]),
+        (128, &["rip"]),
+        (152, &["rsp", "esp", "sp", "spl"]),
+    ];
+
+    REGS.iter()
+        .find(|(_, names)| names.contains(&reg))
+        .map(|(off, _)| *off)
+}
+
+/// Parse a single argument as emitted by `sdt.h`, e.g. `-4@%eax`, `8@-16(%rbp)` or `4@$42`.
+#[cfg(target_arch = "x86_64")]
+fn parse_arg(arg: &str) -> Option<Result<ArgSpec>> {
+    let (size, op) = arg.split_once('@')?;
+
+    let reg = |reg: &str| reg.strip_prefix('%').and_then(reg_off);
+    if let Some(imm) = op.strip_prefix('$') {
+        let imm: i64 = imm.parse().ok()?;
+        Some(ArgSpec::new(size, ArgType::Const, imm as u64, 0))
+    } else if let Some((off, reg_name)) = op.strip_suffix(')').and_then(|op| op.split_once('(')) {
+        let off: i64 = if off.is_empty() { 0 } else { off.parse().ok()? };
+        Some(ArgSpec::new(
+            size,
+            ArgType::RegDeref,
+            off as u64,
+            reg(reg_name)?,
+        ))
+    } else {
+        Some(ArgSpec::new(size, ArgType::Reg, 0, reg(op)?))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn reg_off(reg: &str) -> Option<i16> {
+    // Offsets in `struct user_pt_regs`
+    if reg == "sp" {
+        return Some(31 * 8);
+    }
+
+    match reg.strip_prefix('x')?.parse::<i16>() {
+        Ok(n) if n <= 30 => Some(n * 8),
+        _ => None,
+    }
+}
+
+/// Parse a single argument as emitted by `sdt.h`, e.g. `-4@x0`, `8@[sp, 16]` or `4@42`.
+#[cfg(target_arch = "aarch64")]
+fn parse_arg(arg: &str) -> Option<Result<ArgSpec>> {
+    let (size, op) = arg.split_once('@')?;
+
+    if let Some(op) = op.strip_prefix('[').and_then(|op| op.strip_suffix(']')) {
+        let (reg, off) = match op.split_once(',') {
+            Some((reg, off)) => (reg, off.trim().parse::<i64>().ok()?),
+            None => (op, 0),
+        };
+        Some(ArgSpec::new(
+            size,
+            ArgType::RegDeref,
+            off as u64,
+            reg_off(reg)?,
+        ))
+    } else if let Ok(imm) = op.parse::<i64>() {
+        Some(ArgSpec::new(size, ArgType::Const, imm as u64, 0))
+    } else {
+        Some(ArgSpec::new(size, ArgType::Reg, 0, reg_off(op)?))
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn parse_arg(_arg: &str) -> Option<Result<ArgSpec>> {
+    Some(Err(Error::InvalidInput(
+        "USDT arguments are not supported on this architecture".to_string(),
+    )))
+}
+
+/// Mirrors `struct __bpf_usdt_spec` in `usdt.bpf.h`
+#[derive(Clone, Debug, PartialEq)]
+struct Spec {
+    args: Vec<ArgSpec>,
+}
+
+impl Spec {
+    fn parse(args: &str) -> Result<Self> {
+        let args = args
+            .split_whitespace()
+            .map(|arg| {
+                parse_arg(arg).unwrap_or_else(|| {
+                    Err(Error::InvalidInput(format!(
+                        "Unsupported USDT argument spec {}",
+                        arg
+                    )))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if args.len() > MAX_ARG_CNT {
+            return Err(Error::InvalidInput(format!(
+                "USDT probe has {} arguments, at most {} are supported",
+                args.len(),
+                MAX_ARG_CNT
+            )));
+        }
+
+        Ok(Self { args })
+    }
+
+    /// Serialize to the layout of `struct __bpf_usdt_spec`.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; SPEC_SIZE];
+        for (arg, buf) in self.args.iter().zip(bytes.chunks_mut(ARG_SPEC_SIZE)) {
+            buf[0..8].copy_from_slice(&arg.val_off.to_ne_bytes());
+            buf[8..12].copy_from_slice(&(arg.arg_type as u32).to_ne_bytes());
+            buf[12..14].copy_from_slice(&arg.reg_off.to_ne_bytes());
+            buf[14] = arg.arg_signed as u8;
+            buf[15] = arg.arg_bitshift as u8;
+        }
+
+        // `usdt_cookie` is left 0
+        let arg_cnt_off = MAX_ARG_CNT * ARG_SPEC_SIZE + 8;
+        bytes[arg_cnt_off..arg_cnt_off + 2]
+            .copy_from_slice(&(self.args.len() as i16).to_ne_bytes());
+        bytes
+    }
+}
+
+/// A USDT probe location in an ELF file
+#[derive(Debug)]
+pub(crate) struct Target {
+    /// File offset of the probe instruction
+    pub offset: u64,
+    /// File offset of the semaphore guarding the probe, 0 if there is none
+    pub sema_offset: u64,
+    /// Raw argument spec, e.g. `-4@%eax 8@%rdx`
+    args: String,
+}
+
+fn parse_note<'a>(
+    elf: &Elf,
+    base_addr: Option<u64>,
+    desc: &'a [u8],
+) -> Option<(Target, &'a str, &'a str)> {
+    let addr = |i: usize| {
+        desc.get(i * 8..i * 8 + 8)
+            .map(|b| u64::from_ne_bytes(b.try_into().unwrap()))
+    };
+    let (mut pc, note_base, mut sema) = (addr(0)?, addr(1)?, addr(2)?);
+
+    // Adjust for prelinking, which moves `.stapsdt.base` but not the addresses in the notes
+    if let Some(base_addr) = base_addr {
+        pc = pc.wrapping_add(base_addr.wrapping_sub(note_base));
+        if sema != 0 {
+            sema = sema.wrapping_add(base_addr.wrapping_sub(note_base));
+        }
+    }
+
+    let mut strs = desc.get(24..)?.split(|&b| b == 0);
+    let provider = std::str::from_utf8(strs.next()?).ok()?;
+    let name = std::str::from_utf8(strs.next()?).ok()?;
+    let args = std::str::from_utf8(strs.next()?).ok()?;
+
+    let sema_offset = if sema != 0 {
+        elf::vaddr_to_offset(elf, sema)?
+    } else {
+        0
+    };
+    let target = Target {
+        offset: elf::vaddr_to_offset(elf, pc)?,
+        sema_offset,
+        args: args.to_string(),
+    };
+
+    Some((target, provider, name))
+}
+
+/// Returns all locations of the USDT probe `provider:name` in the ELF file at `path`.
+pub(crate) fn find_targets(path: &Path, provider: &str, name: &str) -> Result<Vec<Target>> {
+    let data = fs::read(path)
+        .map_err(|e| Error::InvalidInput(format!("Failed to read {}: {}", path.display(), e)))?;
+    let elf = Elf::parse(&data).map_err(|e| {
+        Error::InvalidInput(format!("Failed to parse ELF {}: {}", path.display(), e))
+    })?;
+
+    let base_addr = elf
+        .section_headers
+        .iter()
+        .find(|shdr| matches!(elf.shdr_strtab.get(shdr.sh_name), Some(Ok(".stapsdt.base"))))
+        .map(|shdr| shdr.sh_addr);
+
+    let notes = match elf.iter_note_sections(&data, Some(".note.stapsdt")) {
+        Some(notes) => notes,
+        None => {
+            return Err(Error::InvalidInput(format!(
+                "{} has no USDT probes",
+                path.display()
+            )))
+        }
+    };
+
+    let mut targets = Vec::new();
+    for note in notes {
+        let note = note.map_err(|e| {
+            Error::InvalidInput(format!("Invalid USDT note in {}: {}", path.display(), e))
+        })?;
+        if note.n_type != NT_STAPSDT || note.name != "stapsdt" {
+            continue;
+        }
+
+        let (target, note_provider, note_name) = parse_note(&elf, base_addr, note.desc)
+            .ok_or_else(|| {
+                Error::InvalidInput(format!("Invalid USDT note in {}", path.display()))
+            })?;
+        if note_provider == provider && note_name == name {
+            targets.push(target);
+        }
+    }
+
+    if targets.is_empty() {
+        return Err(Error::InvalidInput(format!(
+            "USDT probe {}:{} not found in {}",
+            provider,
+            name,
+            path.display()
+        )));
+    }
+
+    Ok(targets)
+}
+
+lazy_static! {
+    /// Spec ids in use, per spec map id. Programs can't reach their object with libbpf, so this
+    /// can't live in [`Object`].
+    static ref SPEC_IDS: Mutex<HashMap<u32, Vec<bool>>> = Mutex::new(HashMap::new());
+}
+
+/// Spec ids reserved in the spec map of an object. Released on drop.
+pub(crate) struct SpecIds {
+    map_id: u32,
+    ids: Vec<u32>,
+}
+
+impl SpecIds {
+    /// Reserve a spec id for each target and write the specs to the map `specs_fd`.
+    ///
+    /// Returns the spec id of each target, in order. Targets with identical argument specs share
+    /// a spec id.
+    pub(crate) fn write_specs(specs_fd: i32, targets: &[Target]) -> Result<(Self, Vec<u32>)> {
+        let info = query::MapInfo::from_fd(specs_fd)?;
+        if info.value_size as usize != SPEC_SIZE {
+            return Err(Error::InvalidInput(format!(
+                "{} has value size {} instead of {}, is it defined by usdt.bpf.h?",
+                SPECS_MAP_NAME, info.value_size, SPEC_SIZE
+            )));
+        }
+
+        let mut spec_ids = SpecIds {
+            map_id: info.id,
+            ids: Vec::new(),
+        };
+        let mut by_args: HashMap<&str, u32> = HashMap::new();
+        let mut target_ids = Vec::with_capacity(targets.len());
+        for target in targets {
+            if let Some(id) = by_args.get(target.args.as_str()) {
+                target_ids.push(*id);
+                continue;
+            }
+
+            let spec = Spec::parse(&target.args)?;
+            let id = spec_ids.reserve(info.max_entries)?;
+            let bytes = spec.to_bytes();
+            let ret = unsafe {
+                libbpf_sys::bpf_map_update_elem(
+                    specs_fd,
+                    &id as *const u32 as *const _,
+                    bytes.as_ptr() as *const _,
+                    libbpf_sys::BPF_ANY as u64,
+                )
+            };
+            if ret != 0 {
+                return Err(Error::System(nix::errno::errno()))
+                    .context("write usdt spec to", SPECS_MAP_NAME);
+            }
+
+            by_args.insert(&target.args, id);
+            target_ids.push(id);
+        }
+
+        Ok((spec_ids, target_ids))
+    }
+
+    fn reserve(&mut self, max_entries: u32) -> Result<u32> {
+        let mut all_ids = SPEC_IDS.lock().unwrap();
+        let used = all_ids
+            .entry(self.map_id)
+            .or_insert_with(|| vec![false; max_entries as usize]);
+
+        let id = used.iter().position(|used| !used).ok_or_else(|| {
+            Error::InvalidInput(format!(
+                "No free USDT spec ids left, increase BPF_USDT_MAX_SPEC_CNT (currently {})",
+                max_entries
+            ))
+        })?;
+        used[id] = true;
+        self.ids.push(id as u32);
+
+        Ok(id as u32)
+    }
+}
+
+impl Drop for SpecIds {
+    fn drop(&mut self) {
+        let mut all_ids = SPEC_IDS.lock().unwrap();
+        if let Some(used) = all_ids.get_mut(&self.map_id) {
+            for id in &self.ids {
+                used[*id as usize] = false;
+            }
+            if used.iter().all(|used| !used) {
+                all_ids.remove(&self.map_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_parse_args() {
+        let spec = Spec::parse("-4@%eax 8@-16(%rbp) 2@$-3 8@(%rdi) 1@%r12b").unwrap();
+        let expected = [
+            (ArgType::Reg, 0, 80, true, 32),
+            (ArgType::RegDeref, -16i64 as u64, 32, false, 0),
+            (ArgType::Const, -3i64 as u64, 0, false, 48),
+            (ArgType::RegDeref, 0, 112, false, 0),
+            (ArgType::Reg, 0, 24, false, 56),
+        ];
+        assert_eq!(spec.args.len(), expected.len());
+        for (arg, (arg_type, val_off, reg_off, arg_signed, arg_bitshift)) in
+            spec.args.iter().zip(expected.iter())
+        {
+            assert_eq!(arg.arg_type, *arg_type);
+            assert_eq!(arg.val_off, *val_off);
+            assert_eq!(arg.reg_off, *reg_off);
+            assert_eq!(arg.arg_signed, *arg_signed);
+            assert_eq!(arg.arg_bitshift, *arg_bitshift);
+        }
+
+        assert!(Spec::parse("").unwrap().args.is_empty());
+        assert!(Spec::parse("3@%eax").is_err());
+        assert!(Spec::parse("8@%foo").is_err());
+        assert!(Spec::parse("8@8(%rax,%rbx,8)").is_err());
+    }
+
+    #[test]
+    fn test_spec_to_bytes() {
+        let spec = Spec {
+            args: vec![ArgSpec {
+                val_off: 0x1122,
+                arg_type: ArgType::RegDeref,
+                reg_off: 40,
+                arg_signed: true,
+                arg_bitshift: 32,
+            }],
+        };
+        let bytes = spec.to_bytes();
+
+        assert_eq!(bytes.len(), SPEC_SIZE);
+        assert_eq!(bytes[0..8], 0x1122u64.to_ne_bytes());
+        assert_eq!(bytes[8..12], 2u32.to_ne_bytes());
+        assert_eq!(bytes[12..14], 40i16.to_ne_bytes());
+        assert_eq!(bytes[14..16], [1, 32]);
+        assert_eq!(bytes[200..202], 1i16.to_ne_bytes());
+    }
+}
@@ -1,4 +1,5 @@
 use std::ffi::{CStr, CString};
+use std::fs;
 use std::os::raw::c_char;
 use std::path::Path;
 
@@ -27,3 +28,57 @@ pub fn c_ptr_to_string(p: *const c_char) -> Result<String> {
         .map_err(|e| Error::Internal(e.to_string()))?
         .to_owned())
 }
+
+fn parse_cpu_ranges(ranges: &str) -> Result<usize> {
+    let mut num = 0;
+    for range in ranges.trim().split(',') {
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|e| Error::Internal(format!("Failed to parse cpu range {}: {}", range, e)))
+        };
+
+        num += match range.split_once('-') {
+            Some((start, end)) => parse(end)?
+                .checked_sub(parse(start)?)
+                .map(|n| n + 1)
+                .ok_or_else(|| Error::Internal(format!("Invalid cpu range {}", range)))?,
+            None => {
+                parse(range)?;
+                1
+            }
+        };
+    }
+
+    Ok(num)
+}
+
+/// Returns the number of possible CPUs on the system, as reported by
+/// `/sys/devices/system/cpu/possible`.
+///
+/// This is the number of values the kernel stores for each key in a per-cpu [`Map`].
+pub fn num_possible_cpus() -> Result<usize> {
+    let ranges = fs::read_to_string("/sys/devices/system/cpu/possible")
+        .map_err(|e| Error::Internal(format!("Failed to read possible cpus: {}", e)))?;
+
+    parse_cpu_ranges(&ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_ranges() {
+        assert_eq!(parse_cpu_ranges("0\n").unwrap(), 1);
+        assert_eq!(parse_cpu_ranges("0-7\n").unwrap(), 8);
+        assert_eq!(parse_cpu_ranges("0-3,5,7-8").unwrap(), 7);
+        assert!(parse_cpu_ranges("").is_err());
+        assert!(parse_cpu_ranges("0-a").is_err());
+        assert!(parse_cpu_ranges("5-3").is_err());
+    }
+
+    #[test]
+    fn test_num_possible_cpus() {
+        assert!(num_possible_cpus().unwrap() > 0);
+    }
+}
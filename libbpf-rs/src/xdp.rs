@@ -0,0 +1,141 @@
+use std::fmt;
+use std::mem::{size_of, MaybeUninit};
+use std::ptr;
+
+use bitflags::bitflags;
+
+use crate::*;
+
+bitflags! {
+    /// Flags to configure [`Xdp`] operations. Without a mode flag, the kernel uses driver mode
+    /// if the driver supports it and generic (SKB) mode otherwise.
+    pub struct XdpFlags: u32 {
+        const NONE              = 0;
+        /// Fail if a program is already attached in the selected mode
+        const UPDATE_IF_NOEXIST = libbpf_sys::XDP_FLAGS_UPDATE_IF_NOEXIST;
+        /// Generic XDP, which works with every driver
+        const SKB_MODE          = libbpf_sys::XDP_FLAGS_SKB_MODE;
+        /// Native XDP in the driver
+        const DRV_MODE          = libbpf_sys::XDP_FLAGS_DRV_MODE;
+        /// XDP offloaded to the NIC
+        const HW_MODE           = libbpf_sys::XDP_FLAGS_HW_MODE;
+    }
+}
+
+/// Ids of the XDP programs attached to an interface in each mode, `0` if none is.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XdpInfo {
+    pub skb_prog_id: u32,
+    pub drv_prog_id: u32,
+    pub hw_prog_id: u32,
+}
+
+/// XDP programs of a network interface, managed over netlink.
+///
+/// Unlike [`Program::attach_xdp()`], which creates a [`Link`], this allows selecting the
+/// attach mode and works on kernels and drivers without XDP link support. Programs attached
+/// this way stay attached until explicitly detached. Requires `CAP_NET_ADMIN`.
+#[derive(Clone, Debug)]
+pub struct Xdp {
+    ifindex: i32,
+}
+
+impl Xdp {
+    /// XDP programs of the interface with index `ifindex`.
+    pub fn new(ifindex: i32) -> Self {
+        Xdp { ifindex }
+    }
+
+    pub fn ifindex(&self) -> i32 {
+        self.ifindex
+    }
+
+    /// Set the XDP program of the interface, reporting errors as `op` on `name`.
+    fn set_fd(
+        &self,
+        fd: i32,
+        flags: u32,
+        old_fd: Option<i32>,
+        op: &'static str,
+        name: impl fmt::Display,
+    ) -> Result<()> {
+        let ret = match old_fd {
+            Some(old_fd) => {
+                // libbpf rejects non-zero padding. Padding is not preserved when a struct is
+                // moved, even one from `mem::zeroed()`, so the options are zeroed in place and
+                // only written field by field.
+                let mut opts = MaybeUninit::<libbpf_sys::bpf_xdp_set_link_opts>::uninit();
+                unsafe {
+                    let ptr = opts.as_mut_ptr();
+                    ptr::write_bytes(ptr, 0, 1);
+                    (*ptr).sz =
+                        size_of::<libbpf_sys::bpf_xdp_set_link_opts>() as libbpf_sys::size_t;
+                    (*ptr).old_fd = old_fd;
+                    libbpf_sys::bpf_set_link_xdp_fd_opts(
+                        self.ifindex,
+                        fd,
+                        flags | libbpf_sys::XDP_FLAGS_REPLACE,
+                        opts.as_ptr(),
+                    )
+                }
+            }
+            None => unsafe { libbpf_sys::bpf_set_link_xdp_fd(self.ifindex, fd, flags) },
+        };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context(op, name);
+        }
+
+        Ok(())
+    }
+
+    /// Attach `prog`, replacing any program already attached in the same mode unless
+    /// [`XdpFlags::UPDATE_IF_NOEXIST`] is set.
+    pub fn attach(&self, prog: &Program, flags: XdpFlags) -> Result<()> {
+        self.set_fd(prog.fd(), flags.bits, None, "attach xdp", prog.name())
+    }
+
+    /// Atomically replace `old_prog` with `prog`. Fails with `EEXIST` if `old_prog` is not the
+    /// program currently attached in the selected mode.
+    pub fn replace(&self, prog: &Program, old_prog: &Program, flags: XdpFlags) -> Result<()> {
+        self.set_fd(
+            prog.fd(),
+            flags.bits,
+            Some(old_prog.fd()),
+            "replace xdp with",
+            prog.name(),
+        )
+    }
+
+    /// Detach the program attached in the mode selected by `flags`.
+    pub fn detach(&self, flags: XdpFlags) -> Result<()> {
+        self.set_fd(
+            -1,
+            flags.bits,
+            None,
+            "detach xdp from ifindex",
+            self.ifindex,
+        )
+    }
+
+    /// Returns the ids of the programs attached in each mode.
+    pub fn query(&self) -> Result<XdpInfo> {
+        let mut info = libbpf_sys::xdp_link_info::default();
+        let ret = unsafe {
+            libbpf_sys::bpf_get_link_xdp_info(
+                self.ifindex,
+                &mut info,
+                size_of::<libbpf_sys::xdp_link_info>() as libbpf_sys::size_t,
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::System(-ret)).context("query xdp on ifindex", self.ifindex);
+        }
+
+        Ok(XdpInfo {
+            skb_prog_id: info.skb_prog_id,
+            drv_prog_id: info.drv_prog_id,
+            hw_prog_id: info.hw_prog_id,
+        })
+    }
+}
@@ -0,0 +1,17 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+SEC("cgroup_skb/ingress")
+int skb_allow(struct __sk_buff *skb)
+{
+  return 1;
+}
+
+SEC("cgroup_skb/ingress")
+int skb_deny(struct __sk_buff *skb)
+{
+  return 0;
+}
+
+char _license[] SEC("license") = "GPL";
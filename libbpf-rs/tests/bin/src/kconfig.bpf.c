@@ -0,0 +1,36 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+extern int CONFIG_HZ __kconfig;
+extern char CONFIG_LOCALVERSION[8] __kconfig __weak;
+
+struct kconfig_values {
+  u32 hz;
+  char localversion[8];
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, struct kconfig_values);
+} result SEC(".maps");
+
+SEC("xdp")
+int xdp_kconfig(struct xdp_md *ctx)
+{
+  u32 key = 0;
+  struct kconfig_values *val;
+
+  val = bpf_map_lookup_elem(&result, &key);
+  if (!val)
+    return XDP_ABORTED;
+
+  val->hz = CONFIG_HZ;
+  __builtin_memcpy(val->localversion, CONFIG_LOCALVERSION, sizeof(val->localversion));
+
+  return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,18 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+SEC("xdp")
+int xdp_pass(struct xdp_md *ctx)
+{
+  return XDP_PASS;
+}
+
+SEC("classifier")
+int tc_pass(struct __sk_buff *skb)
+{
+  /* TC_ACT_OK */
+  return 0;
+}
+
+char _license[] SEC("license") = "GPL";
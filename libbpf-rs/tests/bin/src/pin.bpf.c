@@ -0,0 +1,26 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, u64);
+  __uint(pinning, LIBBPF_PIN_BY_NAME);
+} pinned SEC(".maps");
+
+SEC("xdp")
+int xdp_count(struct xdp_md *ctx)
+{
+  u32 key = 0;
+  u64 *count;
+
+  count = bpf_map_lookup_elem(&pinned, &key);
+  if (count)
+    __sync_fetch_and_add(count, 1);
+
+  return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";
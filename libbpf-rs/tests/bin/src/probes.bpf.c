@@ -0,0 +1,52 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+/* Not declared by the bpf_helper_defs.h of libbpf 0.4 */
+static __u64 (*bpf_get_attach_cookie)(void *ctx) = (void *) 174;
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, u64);
+} kprobe_cookie SEC(".maps");
+
+/* Every cookie seen by handle__kprobe, for programs attached to several functions */
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __uint(max_entries, 16);
+  __type(key, u64);
+  __type(value, u8);
+} kprobe_cookies SEC(".maps");
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, u64);
+} uprobe_cookie SEC(".maps");
+
+SEC("kprobe/do_nanosleep")
+int handle__kprobe(void *ctx)
+{
+  u32 key = 0;
+  u64 cookie = bpf_get_attach_cookie(ctx);
+  u8 seen = 1;
+
+  bpf_map_update_elem(&kprobe_cookie, &key, &cookie, BPF_ANY);
+  bpf_map_update_elem(&kprobe_cookies, &cookie, &seen, BPF_ANY);
+  return 0;
+}
+
+SEC("uprobe/func")
+int handle__uprobe(void *ctx)
+{
+  u32 key = 0;
+  u64 cookie = bpf_get_attach_cookie(ctx);
+
+  bpf_map_update_elem(&uprobe_cookie, &key, &cookie, BPF_ANY);
+  return 0;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,23 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, u32);
+} values SEC(".maps");
+
+SEC("xdp")
+int xdp_reject(struct xdp_md *ctx)
+{
+  u32 key = 0;
+  u32 *val;
+
+  val = bpf_map_lookup_elem(&values, &key);
+  /* Missing NULL check, which the verifier rejects */
+  return *val;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,34 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+
+struct locked_value {
+  struct bpf_spin_lock lock;
+  u32 count;
+};
+
+struct {
+  __uint(type, BPF_MAP_TYPE_HASH);
+  __uint(max_entries, 1);
+  __type(key, u32);
+  __type(value, struct locked_value);
+} locked SEC(".maps");
+
+SEC("xdp")
+int xdp_count(struct xdp_md *ctx)
+{
+  u32 key = 0;
+  struct locked_value *val;
+
+  val = bpf_map_lookup_elem(&locked, &key);
+  if (!val)
+    return XDP_PASS;
+
+  bpf_spin_lock(&val->lock);
+  val->count++;
+  bpf_spin_unlock(&val->lock);
+
+  return XDP_PASS;
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,31 @@
+#include "vmlinux.h"
+
+#include <bpf/bpf_helpers.h>
+#include <bpf/usdt.bpf.h>
+
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __uint(max_entries, 3);
+  __type(key, u32);
+  __type(value, s64);
+} result SEC(".maps");
+
+static __always_inline void store(u32 key, s64 val)
+{
+  bpf_map_update_elem(&result, &key, &val, 0);
+}
+
+/* The probe in test.rs passes a register, a signed dereferenced register and a
+ * signed constant, one argument of each spec type.
+ */
+SEC("uprobe/usdt")
+int BPF_USDT(handle__usdt, u64 reg, int deref, long cnst)
+{
+  store(0, reg);
+  store(1, deref);
+  store(2, cnst);
+
+  return 0;
+}
+
+char _license[] SEC("license") = "GPL";
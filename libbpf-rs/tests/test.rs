@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::convert::TryInto;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
@@ -9,7 +10,12 @@ use nix::errno;
 use plain::Plain;
 use scopeguard::defer;
 
-use libbpf_rs::{Iter, MapFlags, Object, ObjectBuilder};
+use libbpf_rs::cgroup::{AttachFlags, Cgroup};
+use libbpf_rs::{
+    probe, Iter, KconfigValue, KprobeOpts, KprobesOpts, Map, MapFlags, MapType, Object,
+    ObjectBuilder, Program, ProgramAttachType, ProgramType, TcAttachPoint, TcHook, TcOpts,
+    TypedMap, UprobeOpts, Xdp, XdpFlags,
+};
 
 fn get_test_object_path(filename: &str) -> PathBuf {
     let mut path = PathBuf::new();
@@ -117,6 +123,22 @@ fn test_object_map_key_value_size() {
         .is_err());
 }
 
+#[test]
+fn test_object_map_percpu_on_normal_map() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+    let ncpu = libbpf_rs::num_possible_cpus().expect("failed to get number of cpus");
+
+    assert!(start
+        .lookup_percpu(&[1, 2, 3, 4], MapFlags::empty())
+        .is_err());
+    assert!(start
+        .update_percpu(&[1, 2, 3, 4], &vec![vec![0; 8]; ncpu], MapFlags::empty())
+        .is_err());
+}
+
 #[test]
 fn test_object_map_empty_lookup() {
     bump_rlimit_mlock();
@@ -551,3 +573,1400 @@ fn test_object_task_iter() {
     // Check for init
     assert!(items.iter().any(|&item| item.pid == 1));
 }
+
+#[test]
+fn test_object_typed_map() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // `start` is a u32 -> u64 map
+    assert!(TypedMap::<u64, u64>::new(start).is_err());
+    assert!(TypedMap::<u32, u32>::new(start).is_err());
+
+    let mut typed = TypedMap::<u32, u64>::new(start).expect("failed to create typed map");
+    assert!(typed
+        .lookup(&1, MapFlags::ANY)
+        .expect("failed to read map")
+        .is_none());
+
+    typed
+        .update(&1, &10, MapFlags::ANY)
+        .expect("failed to write");
+    typed
+        .update(&2, &20, MapFlags::ANY)
+        .expect("failed to write");
+    assert_eq!(
+        typed.lookup(&1, MapFlags::ANY).expect("failed to read map"),
+        Some(10)
+    );
+
+    let keys: HashSet<u32> = typed.keys().collect();
+    assert_eq!(keys, [1, 2].iter().cloned().collect());
+
+    let mut entries = typed
+        .iter()
+        .collect::<libbpf_rs::Result<Vec<_>>>()
+        .expect("failed to iterate map");
+    entries.sort_unstable();
+    assert_eq!(entries, vec![(1, 10), (2, 20)]);
+
+    typed.delete(&1).expect("failed to delete key");
+    assert!(typed
+        .lookup(&1, MapFlags::ANY)
+        .expect("failed to read map")
+        .is_none());
+}
+
+#[test]
+fn test_object_map_batch() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    let count: u32 = 100;
+    let keys: Vec<u8> = (0..count).flat_map(|i| i.to_ne_bytes().to_vec()).collect();
+    let values: Vec<u8> = (0..count as u64)
+        .flat_map(|i| (i * 10).to_ne_bytes().to_vec())
+        .collect();
+
+    // Mismatched lengths should be an error
+    assert!(start
+        .update_batch(&keys[1..], &values, MapFlags::ANY, MapFlags::ANY)
+        .is_err());
+    assert!(start
+        .update_batch(&keys, &values[8..], MapFlags::ANY, MapFlags::ANY)
+        .is_err());
+    assert!(start.lookup_batch(0, MapFlags::ANY, MapFlags::ANY).is_err());
+
+    start
+        .update_batch(&keys, &values, MapFlags::ANY, MapFlags::ANY)
+        .expect("failed to batch update");
+
+    let entries = start
+        .lookup_batch(7, MapFlags::ANY, MapFlags::ANY)
+        .expect("failed to create batch iterator")
+        .collect::<libbpf_rs::Result<Vec<_>>>()
+        .expect("failed to batch lookup");
+    assert_eq!(entries.len(), count as usize);
+    for (key, value) in entries {
+        let key = u32::from_ne_bytes(key[..].try_into().unwrap());
+        let value = u64::from_ne_bytes(value[..].try_into().unwrap());
+        assert_eq!(value, key as u64 * 10);
+    }
+
+    // Delete the first half in a batch, then drain the rest
+    start
+        .delete_batch(&keys[..keys.len() / 2], MapFlags::ANY, MapFlags::ANY)
+        .expect("failed to batch delete");
+    assert_eq!(start.keys().count(), count as usize / 2);
+
+    let drained = start
+        .lookup_and_delete_batch(16, MapFlags::ANY, MapFlags::ANY)
+        .expect("failed to create batch iterator")
+        .collect::<libbpf_rs::Result<Vec<_>>>()
+        .expect("failed to batch lookup and delete");
+    assert_eq!(drained.len(), count as usize / 2);
+    assert_eq!(start.keys().count(), 0);
+}
+
+#[test]
+fn test_object_map_iter() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    assert_eq!(start.iter().count(), 0);
+
+    let value = vec![1, 2, 3, 4, 5, 6, 7, 8];
+    for i in 0..10u32 {
+        start
+            .update(&i.to_ne_bytes(), &value, MapFlags::empty())
+            .expect("failed to write");
+    }
+
+    let mut keys = HashSet::new();
+    for entry in start.iter() {
+        let (key, val) = entry.expect("failed to iterate map");
+        assert_eq!(val, value);
+        assert!(keys.insert(key), "duplicate key returned");
+    }
+    assert_eq!(keys.len(), 10);
+
+    let deleted = 3u32.to_ne_bytes().to_vec();
+    start.delete(&deleted).expect("failed to delete key");
+
+    let remaining: HashSet<Vec<u8>> = start
+        .iter()
+        .map(|entry| entry.expect("failed to iterate map").0)
+        .collect();
+    assert_eq!(remaining.len(), 9);
+    assert!(!remaining.contains(&deleted));
+
+    // Delete the key just returned, which makes the kernel restart iteration, along with a key
+    // not returned yet
+    let mut other = Map::from_fd(unsafe { libc::dup(start.fd()) }).expect("failed to dup map");
+    let mut unseen = None;
+    let mut keys = HashSet::new();
+    for entry in start.iter() {
+        let (key, _) = entry.expect("failed to iterate map");
+        assert!(keys.insert(key.clone()), "duplicate key returned");
+
+        if keys.len() == 3 {
+            let k = remaining
+                .iter()
+                .find(|k| !keys.contains(*k))
+                .expect("no key left")
+                .clone();
+            other.delete(&key).expect("failed to delete key");
+            other.delete(&k).expect("failed to delete key");
+            unseen = Some(k);
+        }
+    }
+    assert_eq!(keys.len(), 8);
+    assert!(!keys.contains(&unseen.unwrap()));
+    assert_eq!(start.iter().count(), 7);
+}
+
+#[test]
+fn test_object_map_mmap_not_mmapable() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // Only mmap'able array maps are supported
+    assert!(start.mmap::<u64>().is_err());
+    assert!(start.mmap_mut::<u64>().is_err());
+}
+
+#[test]
+fn test_map_mmap() {
+    bump_rlimit_mlock();
+
+    let mut map = Map::create(MapType::Array, 4, 8, 4, libbpf_sys::BPF_F_MMAPABLE)
+        .expect("failed to create map");
+
+    {
+        let mut array = map.mmap_mut::<u64>().expect("failed to mmap map");
+        assert_eq!(array.len(), 4);
+        array.set(1, 0xdeadbeef).expect("failed to write");
+        assert!(array.set(4, 0).is_err());
+        assert_eq!(array.get(1), Some(0xdeadbeef));
+    }
+
+    let val = map
+        .lookup(&1u32.to_ne_bytes(), MapFlags::ANY)
+        .expect("failed to lookup")
+        .expect("failed to find value");
+    assert_eq!(val, 0xdeadbeefu64.to_ne_bytes());
+
+    map.update(&2u32.to_ne_bytes(), &42u64.to_ne_bytes(), MapFlags::ANY)
+        .expect("failed to update");
+    let array = map.mmap::<u64>().expect("failed to mmap map");
+    assert_eq!(array.iter().collect::<Vec<_>>(), vec![0, 0xdeadbeef, 42, 0]);
+}
+
+#[test]
+fn test_object_map_queue_ops_on_hash() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    // Keyless operations are only supported for Queue and Stack maps
+    assert!(start
+        .push(&[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::ANY)
+        .is_err());
+    assert!(start.pop().is_err());
+    assert!(start.peek().is_err());
+}
+
+/// Round-trip a distinct value for each possible cpu through a per-cpu map with 4-byte values,
+/// which the kernel pads to 8 bytes per cpu.
+fn check_percpu_round_trip(map_type: MapType) {
+    let mut map = Map::create(map_type.clone(), 4, 4, 4, 0).expect("failed to create map");
+    let ncpu = libbpf_rs::num_possible_cpus().expect("failed to get number of cpus");
+    let key = 1u32.to_ne_bytes();
+
+    let values: Vec<Vec<u8>> = (0..ncpu)
+        .map(|cpu| (0x1000 + cpu as u32).to_ne_bytes().to_vec())
+        .collect();
+    map.update_percpu(&key, &values, MapFlags::ANY)
+        .expect("failed to update per-cpu values");
+    assert_eq!(
+        map.lookup_percpu(&key, MapFlags::ANY)
+            .expect("failed to lookup per-cpu values")
+            .expect("failed to find per-cpu values"),
+        values,
+        "{}",
+        map_type
+    );
+
+    assert!(matches!(
+        map.lookup(&key, MapFlags::ANY),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        map.update(&key, &values[0], MapFlags::ANY),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_map_percpu_array() {
+    bump_rlimit_mlock();
+
+    check_percpu_round_trip(MapType::PercpuArray);
+}
+
+#[test]
+fn test_map_percpu_hash() {
+    bump_rlimit_mlock();
+
+    check_percpu_round_trip(MapType::PercpuHash);
+}
+
+#[test]
+fn test_map_queue() {
+    bump_rlimit_mlock();
+
+    let mut queue = Map::create(MapType::Queue, 0, 4, 8, 0).expect("failed to create map");
+
+    assert_eq!(queue.pop().expect("failed to pop"), None);
+    assert_eq!(queue.peek().expect("failed to peek"), None);
+
+    for i in 1..=3u32 {
+        queue
+            .push(&i.to_ne_bytes(), MapFlags::ANY)
+            .expect("failed to push");
+    }
+
+    // First in, first out
+    assert_eq!(
+        queue.peek().expect("failed to peek"),
+        Some(1u32.to_ne_bytes().to_vec())
+    );
+    for i in 1..=3u32 {
+        assert_eq!(
+            queue.pop().expect("failed to pop"),
+            Some(i.to_ne_bytes().to_vec())
+        );
+    }
+    assert_eq!(queue.pop().expect("failed to pop"), None);
+}
+
+#[test]
+fn test_map_stack() {
+    bump_rlimit_mlock();
+
+    let mut stack = Map::create(MapType::Stack, 0, 4, 8, 0).expect("failed to create map");
+
+    assert_eq!(stack.pop().expect("failed to pop"), None);
+    assert_eq!(stack.peek().expect("failed to peek"), None);
+
+    for i in 1..=3u32 {
+        stack
+            .push(&i.to_ne_bytes(), MapFlags::ANY)
+            .expect("failed to push");
+    }
+
+    // Last in, first out
+    assert_eq!(
+        stack.peek().expect("failed to peek"),
+        Some(3u32.to_ne_bytes().to_vec())
+    );
+    for i in (1..=3u32).rev() {
+        assert_eq!(
+            stack.pop().expect("failed to pop"),
+            Some(i.to_ne_bytes().to_vec())
+        );
+    }
+    assert_eq!(stack.pop().expect("failed to pop"), None);
+}
+
+#[test]
+fn test_object_map_lock_without_spin_lock() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let start = obj.map_mut("start").expect("failed to find map");
+
+    assert!(!start.has_spin_lock().expect("failed to inspect map BTF"));
+    assert!(matches!(
+        start.lookup(&[1, 2, 3, 4], MapFlags::LOCK),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+    assert!(matches!(
+        start.update(&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::LOCK),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+}
+
+#[test]
+fn test_object_map_lock() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("spinlock.bpf.o");
+    let locked = obj.map_mut("locked").expect("failed to find map");
+    assert!(locked.has_spin_lock().expect("failed to inspect map BTF"));
+
+    // struct locked_value { struct bpf_spin_lock lock; u32 count; }
+    let key = 0u32.to_ne_bytes();
+    let mut value = vec![0; 4];
+    value.extend_from_slice(&41u32.to_ne_bytes());
+    locked
+        .update(&key, &value, MapFlags::LOCK)
+        .expect("failed to update with lock");
+    assert_eq!(
+        locked
+            .lookup(&key, MapFlags::LOCK)
+            .expect("failed to lookup with lock")
+            .expect("failed to find value"),
+        value
+    );
+
+    // The program increments the count under the lock
+    let prog = obj.prog_mut("xdp_count").expect("failed to find program");
+    prog.test_run(&[0; 64], None, 1)
+        .expect("failed to test run");
+    let locked = obj.map("locked").expect("failed to find map");
+    let value = locked
+        .lookup(&key, MapFlags::LOCK)
+        .expect("failed to lookup with lock")
+        .expect("failed to find value");
+    assert_eq!(value[4..], 42u32.to_ne_bytes());
+
+    // Maps not opened through libbpf find the value type in the map info
+    let other = Map::from_fd(unsafe { libc::dup(locked.fd()) }).expect("failed to dup map");
+    assert!(other.has_spin_lock().expect("failed to inspect map BTF"));
+}
+
+#[test]
+fn test_map_create() {
+    bump_rlimit_mlock();
+
+    let mut map = Map::create(MapType::Hash, 4, 8, 16, 0).expect("failed to create map");
+    assert!(map.map_type() == MapType::Hash);
+    assert_eq!(map.key_size(), 4);
+    assert_eq!(map.value_size(), 8);
+
+    map.update(&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::ANY)
+        .expect("failed to write");
+    assert_eq!(
+        map.lookup(&[1, 2, 3, 4], MapFlags::ANY)
+            .expect("failed to read map")
+            .expect("failed to find key"),
+        &[1, 2, 3, 4, 5, 6, 7, 8]
+    );
+
+    // Invalid sizes are rejected by the kernel
+    assert!(Map::create(MapType::Hash, 0, 8, 16, 0).is_err());
+}
+
+#[test]
+fn test_map_in_map() {
+    bump_rlimit_mlock();
+
+    let inner_template = Map::create(MapType::Array, 4, 8, 1, 0).expect("failed to create map");
+    let mut outer = Map::create_map_in_map(MapType::ArrayOfMaps, 4, &inner_template, 4, 0)
+        .expect("failed to create map in map");
+    assert!(Map::create_map_in_map(MapType::Hash, 4, &inner_template, 4, 0).is_err());
+
+    let mut inner = Map::create(MapType::Array, 4, 8, 1, 0).expect("failed to create map");
+    inner
+        .update(&[0, 0, 0, 0], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::ANY)
+        .expect("failed to write");
+
+    let key = 1u32.to_ne_bytes();
+    assert!(outer
+        .lookup_inner_map(&key)
+        .expect("failed to read map")
+        .is_none());
+    outer
+        .update_inner_map(&key, &inner, MapFlags::ANY)
+        .expect("failed to store inner map");
+
+    let found = outer
+        .lookup_inner_map(&key)
+        .expect("failed to read map")
+        .expect("failed to find inner map");
+    assert!(found.map_type() == MapType::Array);
+    assert_eq!(found.value_size(), 8);
+    assert_eq!(
+        found
+            .lookup(&[0, 0, 0, 0], MapFlags::ANY)
+            .expect("failed to read map")
+            .expect("failed to find key"),
+        &[1, 2, 3, 4, 5, 6, 7, 8]
+    );
+
+    // Inner map operations require a map-in-map
+    assert!(inner
+        .update_inner_map(&[0, 0, 0, 0], &found, MapFlags::ANY)
+        .is_err());
+}
+
+#[test]
+fn test_map_from_pinned_path() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let map = obj.map_mut("start").expect("failed to find map");
+    let path = "/sys/fs/bpf/mypinnedmap";
+
+    assert!(Map::from_pinned_path(path).is_err());
+
+    map.pin(path).expect("failed to pin map");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    let mut pinned = Map::from_pinned_path(path).expect("failed to open pinned map");
+    assert_eq!(pinned.name(), "start");
+    assert!(pinned.map_type() == MapType::Hash);
+    assert_eq!(pinned.key_size(), 4);
+    assert_eq!(pinned.value_size(), 8);
+
+    // Both handles refer to the same map
+    pinned
+        .update(&[1, 2, 3, 4], &[1, 2, 3, 4, 5, 6, 7, 8], MapFlags::ANY)
+        .expect("failed to write");
+    assert!(map
+        .lookup(&[1, 2, 3, 4], MapFlags::ANY)
+        .expect("failed to read map")
+        .is_some());
+
+    let info = libbpf_rs::query::MapInfoIter::default()
+        .find(|info| info.name == "start")
+        .expect("failed to find map info");
+    let by_id = Map::from_id(info.id).expect("failed to open map by id");
+    assert_eq!(by_id.name(), "start");
+
+    map.unpin(path).expect("failed to unpin map");
+}
+
+#[test]
+fn test_program_from_pinned_path() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+    let path = "/sys/fs/bpf/mypinnedprog";
+
+    prog.pin(path).expect("failed to pin prog");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_file(path);
+    }
+
+    let mut pinned = Program::from_pinned_path(path).expect("failed to open pinned prog");
+    assert_eq!(pinned.prog_type().to_string(), prog.prog_type().to_string());
+    assert_eq!(pinned.section(), "");
+    // Kernel program names are truncated
+    assert!("handle__sched_wakeup".starts_with(pinned.name()));
+    assert!(pinned.attach().is_err());
+
+    pinned.unpin(path).expect("failed to unpin prog");
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn test_object_open_map_config() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let start = open_obj.map_mut("start").expect("failed to find map");
+
+    assert_eq!(start.name().expect("failed to get map name"), "start");
+    assert!(start.map_type() == MapType::Hash);
+    assert_eq!(start.key_size(), 4);
+    assert_eq!(start.value_size(), 8);
+    assert_eq!(start.max_entries(), 10240);
+
+    start
+        .set_map_type(MapType::LruHash)
+        .expect("failed to set map type");
+    start
+        .set_map_flags(libbpf_sys::BPF_F_NO_COMMON_LRU)
+        .expect("failed to set map flags");
+    start
+        .set_max_entries(64)
+        .expect("failed to set max entries");
+    assert!(start.map_type() == MapType::LruHash);
+    assert_eq!(start.map_flags(), libbpf_sys::BPF_F_NO_COMMON_LRU);
+    assert_eq!(start.max_entries(), 64);
+
+    let obj = open_obj.load().expect("failed to load object");
+    let start = obj.map("start").expect("failed to find map");
+    assert!(start.map_type() == MapType::LruHash);
+}
+
+#[test]
+fn test_object_pin_maps() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let path = "/sys/fs/bpf/myobjmaps";
+    let map_path = Path::new(path).join("start");
+
+    obj.pin_maps(path).expect("failed to pin maps");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_dir_all(path);
+    }
+
+    assert!(map_path.exists());
+    let pinned = Map::from_pinned_path(&map_path).expect("failed to open pinned map");
+    assert_eq!(pinned.name(), "start");
+
+    obj.unpin_maps(path).expect("failed to unpin maps");
+    assert!(!map_path.exists());
+}
+
+#[test]
+fn test_object_pin_all() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let path = "/sys/fs/bpf/myobj";
+
+    obj.pin_all(path).expect("failed to pin object");
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_dir_all(path);
+    }
+
+    assert!(Path::new(path).join("start").exists());
+    assert!(Path::new(path).join("handle__sched_wakeup").exists());
+
+    obj.unpin_all(path).expect("failed to unpin object");
+    assert!(!Path::new(path).join("start").exists());
+    assert!(!Path::new(path).join("handle__sched_wakeup").exists());
+}
+
+#[test]
+fn test_object_pin_root_path() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("pin.bpf.o");
+    let path = "/sys/fs/bpf/myroot";
+    let map_path = Path::new(path).join("pinned");
+    let mut builder = ObjectBuilder::default();
+
+    assert!(builder.pin_root_path("/sys/fs/bpf/\0").is_err());
+
+    // Backup cleanup method in case test errors
+    defer! {
+        let _ = fs::remove_dir_all(path);
+    }
+
+    let mut obj = builder
+        .pin_root_path(path)
+        .expect("failed to set pin root path")
+        .open_file(obj_path)
+        .expect("failed to open object")
+        .load()
+        .expect("failed to load object");
+
+    // Maps with `LIBBPF_PIN_BY_NAME` are pinned under the root path on load
+    assert!(map_path.exists());
+    let pinned = Map::from_pinned_path(&map_path).expect("failed to open pinned map");
+    assert_eq!(pinned.name(), "pinned");
+
+    obj.unpin_maps(path).expect("failed to unpin maps");
+    assert!(!map_path.exists());
+}
+
+#[test]
+fn test_object_kconfig() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("kconfig.bpf.o");
+    let mut builder = ObjectBuilder::default();
+
+    assert!(builder
+        .kconfig_option("HZ", KconfigValue::Int(1000))
+        .is_err());
+    assert!(builder
+        .kconfig_option("CONFIG_LOCALVERSION", KconfigValue::Str("a\"b".to_string()))
+        .is_err());
+    assert!(builder.kconfig("CONFIG_BPF=y\0").is_err());
+
+    builder
+        .kconfig("CONFIG_BPF=y\nCONFIG_HZ=250\n")
+        .expect("failed to set kconfig")
+        .kconfig_option("CONFIG_HZ", KconfigValue::Int(1000))
+        .expect("failed to set kconfig option")
+        .kconfig_option("CONFIG_LOCALVERSION", KconfigValue::Str("-ci".to_string()))
+        .expect("failed to set kconfig option");
+
+    let mut obj = builder
+        .open_file(obj_path)
+        .expect("failed to open object")
+        .load()
+        .expect("failed to load object");
+
+    // The program copies the values it sees into `result`
+    obj.prog_mut("xdp_kconfig")
+        .expect("failed to find program")
+        .test_run(&[0; 64], None, 1)
+        .expect("failed to test run program");
+
+    let val = obj
+        .map("result")
+        .expect("failed to find map")
+        .lookup(&0u32.to_ne_bytes(), MapFlags::ANY)
+        .expect("failed to lookup")
+        .expect("failed to find value");
+    assert_eq!(val[0..4], 1000u32.to_ne_bytes());
+    assert_eq!(val[4..12], *b"-ci\0\0\0\0\0");
+}
+
+#[test]
+fn test_object_program_rejected() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("reject.bpf.o");
+    let ret = ObjectBuilder::default()
+        .truncate_verifier_log(4096)
+        .open_file(obj_path)
+        .expect("failed to open object")
+        .load();
+
+    match ret {
+        Err(libbpf_rs::Error::ProgramRejected { name, log }) => {
+            assert_eq!(name, "xdp_reject");
+            assert!(
+                log.contains("invalid mem access"),
+                "unexpected log: {}",
+                log
+            );
+            assert!(log.len() <= 4096);
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("verifier accepted program"),
+    }
+}
+
+#[test]
+fn test_object_btf_custom() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let btf_path = "/sys/kernel/btf/vmlinux";
+
+    ObjectBuilder::default()
+        .btf_custom_path(btf_path)
+        .expect("failed to set custom btf path")
+        .open_file(&obj_path)
+        .expect("failed to open object")
+        .load()
+        .expect("failed to load object with custom btf path");
+
+    let btf = fs::read(btf_path).expect("failed to read btf");
+    ObjectBuilder::default()
+        .btf_custom_data(&btf)
+        .open_file(&obj_path)
+        .expect("failed to open object")
+        .load()
+        .expect("failed to load object with custom btf data");
+
+    // CO-RE relocations fail without valid BTF
+    assert!(ObjectBuilder::default()
+        .btf_custom_path("/does/not/exist")
+        .expect("failed to set custom btf path")
+        .open_file(&obj_path)
+        .expect("failed to open object")
+        .load()
+        .is_err());
+}
+
+#[test]
+fn test_error_context() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("runqslower.bpf.o");
+    let prog = obj
+        .prog_mut("handle__sched_wakeup")
+        .expect("failed to find program");
+
+    let err = match prog.attach_kprobe(false, "does_not_exist_at_all") {
+        Ok(_) => panic!("attached to missing kprobe"),
+        Err(e) => e,
+    };
+    match &err {
+        libbpf_rs::Error::Operation { op, name, .. } => {
+            assert_eq!(*op, "attach kprobe");
+            assert_eq!(name, "handle__sched_wakeup");
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    assert!(err.errno().is_some());
+
+    let err = match Map::from_pinned_path("/sys/fs/bpf/does_not_exist") {
+        Ok(_) => panic!("opened missing map"),
+        Err(e) => e,
+    };
+    assert_eq!(err.errno(), Some(libc::ENOENT));
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("/sys/fs/bpf/does_not_exist"));
+
+    let io_err: std::io::Error = err.into();
+    assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_object_program_autoload() {
+    bump_rlimit_mlock();
+
+    let obj_path = get_test_object_path("runqslower.bpf.o");
+    let mut open_obj = ObjectBuilder::default()
+        .open_file(obj_path)
+        .expect("failed to open object");
+    let prog = open_obj
+        .prog_mut("handle__sched_switch")
+        .expect("failed to find program");
+
+    assert_eq!(
+        prog.name().expect("failed to get program name"),
+        "handle__sched_switch"
+    );
+    assert_eq!(
+        prog.section().expect("failed to get program section"),
+        "tp_btf/sched_switch"
+    );
+    assert!(prog.autoload());
+
+    prog.set_autoload(false).expect("failed to set autoload");
+    assert!(!prog.autoload());
+
+    let mut obj = open_obj.load().expect("failed to load object");
+    assert!(obj.prog("handle__sched_wakeup").unwrap().fd() >= 0);
+
+    let prog = obj
+        .prog_mut("handle__sched_switch")
+        .expect("failed to find program");
+    assert!(prog.fd() < 0);
+    assert!(prog.attach().is_err());
+}
+
+#[test]
+fn test_probe() {
+    bump_rlimit_mlock();
+
+    assert!(probe::is_prog_type_supported(ProgramType::SocketFilter).unwrap());
+    assert!(probe::is_prog_type_supported(ProgramType::Tracing).is_err());
+    assert!(probe::is_map_type_supported(MapType::Hash).unwrap());
+    assert!(probe::is_map_type_supported(MapType::Unknown).is_err());
+    assert!(probe::is_helper_supported(
+        ProgramType::SocketFilter,
+        libbpf_rs::libbpf_sys::BPF_FUNC_map_lookup_elem
+    )
+    .unwrap());
+
+    let prog_types = probe::supported_prog_types();
+    assert!(prog_types
+        .iter()
+        .any(|ty| ty.to_string() == ProgramType::SocketFilter.to_string()));
+    assert!(probe::supported_map_types().contains(&MapType::Array));
+
+    // runqslower.bpf.o is a CO-RE object, so tests already require kernel BTF
+    assert!(probe::has_kernel_btf());
+}
+
+#[test]
+fn test_program_test_run() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("pass.bpf.o");
+    let prog = obj.prog_mut("xdp_pass").expect("failed to find program");
+
+    let input = [0xaa; 64];
+    let res = prog.test_run(&input, None, 3).expect("failed to test run");
+    // XDP_PASS
+    assert_eq!(res.return_value, 2);
+    assert_eq!(res.data_out, input);
+    assert!(res.ctx_out.is_none());
+
+    assert!(matches!(
+        prog.test_run(&input, None, u32::MAX),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+
+    let prog = obj.prog_mut("tc_pass").expect("failed to find program");
+    let res = prog.test_run(&input, None, 1).expect("failed to test run");
+    assert_eq!(res.return_value, 0);
+}
+
+/// Returns the cookie last stored in `map` by a program of `probes.bpf.o`.
+fn probe_cookie(obj: &Object, map: &str) -> u64 {
+    let val = obj
+        .map(map)
+        .expect("failed to find map")
+        .lookup(&0u32.to_ne_bytes(), MapFlags::ANY)
+        .expect("failed to lookup")
+        .expect("failed to find value");
+    u64::from_ne_bytes(val.try_into().expect("invalid value size"))
+}
+
+/// Returns the kernel symbol preceding `func` and the offset of `func` from it.
+fn preceding_ksym(func: &str) -> (String, usize) {
+    let kallsyms = fs::read_to_string("/proc/kallsyms").expect("failed to read kallsyms");
+    let syms: Vec<(usize, &str)> = kallsyms
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let kind = fields.next()?;
+            let name = fields.next()?;
+            // Only core kernel text symbols, module symbols have a fourth field
+            match (kind, fields.next()) {
+                ("t" | "T", None) => Some((addr, name)),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let addr = syms
+        .iter()
+        .find(|(_, name)| *name == func)
+        .map(|(addr, _)| *addr)
+        .expect("failed to find symbol");
+    assert_ne!(addr, 0, "kallsyms addresses are hidden");
+    let (prev_addr, prev_name) = syms
+        .iter()
+        .filter(|(a, _)| *a < addr)
+        .max_by_key(|(a, _)| *a)
+        .expect("failed to find preceding symbol");
+
+    (prev_name.to_string(), addr - prev_addr)
+}
+
+#[test]
+fn test_program_attach_kprobe_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("probes.bpf.o");
+    let prog = obj
+        .prog_mut("handle__kprobe")
+        .expect("failed to find program");
+
+    let opts = KprobeOpts {
+        cookie: 42,
+        ..Default::default()
+    };
+    let link = prog
+        .attach_kprobe_with_opts("do_nanosleep", &opts)
+        .expect("failed to attach kprobe with cookie");
+    assert!(link.get_fd() >= 0);
+    std::thread::sleep(Duration::from_millis(1));
+    drop(link);
+    assert_eq!(probe_cookie(&obj, "kprobe_cookie"), 42);
+
+    // Probe the start of do_nanosleep as an offset into the preceding function, which is
+    // guaranteed to be an instruction boundary
+    let (func, offset) = preceding_ksym("do_nanosleep");
+    let opts = KprobeOpts {
+        offset,
+        cookie: 43,
+        ..Default::default()
+    };
+    let prog = obj
+        .prog_mut("handle__kprobe")
+        .expect("failed to find program");
+    let link = prog
+        .attach_kprobe_with_opts(&func, &opts)
+        .expect("failed to attach kprobe at offset");
+    std::thread::sleep(Duration::from_millis(1));
+    drop(link);
+    assert_eq!(probe_cookie(&obj, "kprobe_cookie"), 43);
+
+    let prog = obj
+        .prog_mut("handle__kprobe")
+        .expect("failed to find program");
+    let opts = KprobeOpts {
+        retprobe: true,
+        cookie: 44,
+        ..Default::default()
+    };
+    let link = prog
+        .attach_kprobe_with_opts("do_nanosleep", &opts)
+        .expect("failed to attach kretprobe");
+    std::thread::sleep(Duration::from_millis(1));
+    drop(link);
+    assert_eq!(probe_cookie(&obj, "kprobe_cookie"), 44);
+
+    let prog = obj
+        .prog_mut("handle__kprobe")
+        .expect("failed to find program");
+    let opts = KprobeOpts {
+        retprobe: true,
+        offset: 4,
+        ..Default::default()
+    };
+    assert!(matches!(
+        prog.attach_kprobe_with_opts("do_nanosleep", &opts),
+        Err(libbpf_rs::Error::InvalidInput(_))
+    ));
+
+    assert!(prog
+        .attach_kprobe_with_opts("this_function_does_not_exist", &KprobeOpts::default())
+        .is_err());
+}
+
+#[test]
+fn test_program_attach_kprobes() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("probes.bpf.o");
+    let prog = obj
+        .prog_mut("handle__kprobe")
+        .expect("failed to find program");
+
+    let funcs = ["do_nanosleep", "hrtimer_nanosleep"];
+    let opts = KprobesOpts {
+        retprobe: false,
+        cookies: vec![1, 2],
+    };
+    let link = prog
+        .attach_kprobes(&funcs, &opts)
+        .expect("failed to attach kprobes");
+    assert_eq!(link.get_fd(), -1);
+
+    let opts = KprobesOpts {
+        retprobe: false,
+        cookies: vec![1],
+    };
+    assert!(prog.attach_kprobes(&funcs, &opts).is_err());
+
+    // nanosleep() goes through both functions
+    std::thread::sleep(Duration::from_millis(1));
+    drop(link);
+
+    let cookies = obj.map_mut("kprobe_cookies").expect("failed to find map");
+    for cookie in [1u64, 2] {
+        assert!(cookies
+            .lookup(&cookie.to_ne_bytes(), MapFlags::ANY)
+            .expect("failed to lookup")
+            .is_some());
+    }
+
+    // Dropping the link detached both probes
+    cookies
+        .delete(&1u64.to_ne_bytes())
+        .expect("failed to delete cookie");
+    std::thread::sleep(Duration::from_millis(1));
+    assert!(cookies
+        .lookup(&1u64.to_ne_bytes(), MapFlags::ANY)
+        .expect("failed to lookup")
+        .is_none());
+}
+
+/// Returns the offset of the code at `addr` in the executable of this process.
+fn exe_file_offset(addr: usize) -> usize {
+    let exe = fs::read_link("/proc/self/exe").expect("failed to read exe path");
+    let maps = fs::read_to_string("/proc/self/maps").expect("failed to read maps");
+    maps.lines()
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (start, end) = fields[0].split_once('-')?;
+            let start = usize::from_str_radix(start, 16).ok()?;
+            let end = usize::from_str_radix(end, 16).ok()?;
+            let offset = usize::from_str_radix(fields[2], 16).ok()?;
+            if (start..end).contains(&addr) && fields.get(5) == Some(&exe.to_str()?) {
+                Some(addr - start + offset)
+            } else {
+                None
+            }
+        })
+        .expect("failed to find mapping")
+}
+
+#[test]
+fn test_program_attach_uprobe_with_opts() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("probes.bpf.o");
+    let prog = obj
+        .prog_mut("handle__uprobe")
+        .expect("failed to find program");
+
+    let opts = UprobeOpts {
+        retprobe: false,
+        cookie: 7,
+    };
+    let offset = exe_file_offset(uprobe_cookie_target as *const () as usize);
+    let link = prog
+        .attach_uprobe_with_opts(-1, "/proc/self/exe", offset, &opts)
+        .expect("failed to attach uprobe");
+    assert!(link.get_fd() >= 0);
+
+    uprobe_cookie_target();
+    assert_eq!(probe_cookie(&obj, "uprobe_cookie"), 7);
+}
+
+#[no_mangle]
+#[inline(never)]
+extern "C" fn uprobe_cookie_target() {
+    // Keep the function from being merged with `uprobe_target()`
+    unsafe { std::ptr::read_volatile(&0u8) };
+}
+
+#[no_mangle]
+#[inline(never)]
+extern "C" fn uprobe_target() {}
+
+#[test]
+fn test_program_attach_uprobe_symbol() {
+    bump_rlimit_mlock();
+
+    let mut obj = get_test_object("probes.bpf.o");
+    let prog = obj
+        .prog_mut("handle__uprobe")
+        .expect("failed to find program");
+
+    let _link = prog
+        .attach_uprobe_symbol(false, -1, "/proc/self/exe", "uprobe_target")
+        .expect("failed to attach uprobe to symbol");
+    uprobe_target();
+
+    // Resolved through ld.so.cache
+    let _link = prog
+        .attach_uprobe_symbol(true, -1, "libc.so.6", "malloc")
+        .expect("failed to attach uretprobe to libc");
+
+    assert!(prog
+        .attach_uprobe_symbol(false, -1, "/proc/self/exe", "no_such_symbol")
+        .is_err());
+}
+
+// The USDT semaphore and probe are emitted by hand the way <sys/sdt.h> does it
+#[cfg(target_arch = "x86_64")]
+std::arch::global_asm!(
+    ".pushsection .probes, \"aw\", @progbits",
+    ".globl libbpf_rs_usdt_semaphore",
+    ".balign 2",
+    "libbpf_rs_usdt_semaphore: .2byte 0",
+    ".popsection",
+);
+
+#[cfg(target_arch = "x86_64")]
+extern "C" {
+    static libbpf_rs_usdt_semaphore: u16;
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline(never)]
+fn usdt_target(reg: u64, deref: &[i32; 2]) {
+    unsafe {
+        std::arch::asm!(
+            "990: nop",
+            ".pushsection .note.stapsdt, \"\", \"note\"",
+            ".balign 4",
+            ".4byte 992f-991f, 994f-993f, 3",
+            "991: .asciz \"stapsdt\"",
+            "992: .balign 4",
+            "993: .8byte 990b",
+            ".8byte _.stapsdt.base",
+            ".8byte libbpf_rs_usdt_semaphore",
+            ".asciz \"libbpf_rs\"",
+            ".asciz \"test\"",
+            ".asciz \"8@{0} -4@({1}) -4@$-5\"",
+            "994: .balign 4",
+            ".popsection",
+            ".ifndef _.stapsdt.base",
+            ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat",
+            ".weak _.stapsdt.base",
+            ".hidden _.stapsdt.base",
+            "_.stapsdt.base: .space 1",
+            ".size _.stapsdt.base, 1",
+            ".popsection",
+            ".endif",
+            in(reg) reg,
+            in(reg) deref.as_ptr(),
+            options(att_syntax, nostack, preserves_flags),
+        );
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_program_attach_usdt() {
+    bump_rlimit_mlock();
+
+    let semaphore = || unsafe { std::ptr::read_volatile(&libbpf_rs_usdt_semaphore) };
+
+    let mut obj = get_test_object("usdt.bpf.o");
+    let prog = obj
+        .prog_mut("handle__usdt")
+        .expect("failed to find program");
+
+    assert!(prog
+        .attach_usdt(-1, "/proc/self/exe", "libbpf_rs", "no_such_probe")
+        .is_err());
+
+    let link = prog
+        .attach_usdt(-1, "/proc/self/exe", "libbpf_rs", "test")
+        .expect("failed to attach usdt");
+    // The kernel may count the probe more than once, e.g. once per mapping of the binary
+    assert_ne!(semaphore(), 0);
+
+    // The second element sits in the upper half of the 8 bytes read for the 4 byte argument
+    // and has to be shifted out before sign extension
+    usdt_target(0xdeadbeef, &[-2, 0x1234]);
+
+    let result = obj.map("result").expect("failed to find map");
+    let arg = |idx: u32| {
+        let val = result
+            .lookup(&idx.to_ne_bytes(), MapFlags::ANY)
+            .expect("failed to lookup")
+            .expect("failed to find result");
+        i64::from_ne_bytes(val.try_into().unwrap())
+    };
+    assert_eq!(arg(0), 0xdeadbeef);
+    assert_eq!(arg(1), -2);
+    assert_eq!(arg(2), -5);
+
+    drop(link);
+    assert_eq!(semaphore(), 0);
+}
+
+/// Move the calling thread into a new network namespace with a veth pair and return the
+/// ifindex of one end. The namespace goes away with the test thread.
+fn setup_veth_netns() -> i32 {
+    nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNET).expect("failed to unshare netns");
+
+    let status = std::process::Command::new("ip")
+        .args([
+            "link", "add", "veth0", "type", "veth", "peer", "name", "veth1",
+        ])
+        .status()
+        .expect("failed to run ip");
+    assert!(status.success());
+
+    nix::net::if_::if_nametoindex("veth0").expect("failed to find veth0") as i32
+}
+
+#[test]
+fn test_tc() {
+    bump_rlimit_mlock();
+
+    let ifindex = setup_veth_netns();
+    let obj = get_test_object("pass.bpf.o");
+    let prog = obj.prog("tc_pass").expect("failed to find program");
+
+    let hook = TcHook::new(ifindex);
+    hook.create().expect("failed to create clsact qdisc");
+    // Creating an existing qdisc is fine
+    hook.create().expect("failed to create clsact qdisc again");
+
+    let opts = TcOpts {
+        handle: 1,
+        priority: 1,
+    };
+    let mut ingress = hook
+        .attach(prog, TcAttachPoint::Ingress, &opts)
+        .expect("failed to attach ingress filter");
+    assert_eq!(ingress.handle(), 1);
+    assert_eq!(ingress.priority(), 1);
+    assert_eq!(
+        hook.query(TcAttachPoint::Ingress, 1, 1)
+            .expect("failed to query ingress filter"),
+        ingress.prog_id()
+    );
+
+    // Same handle and priority only succeeds through replace
+    assert!(hook.attach(prog, TcAttachPoint::Ingress, &opts).is_err());
+    let obj2 = get_test_object("pass.bpf.o");
+    let prog2 = obj2.prog("tc_pass").expect("failed to find program");
+    let old_id = ingress.prog_id();
+    ingress
+        .replace(prog2)
+        .expect("failed to replace ingress filter");
+    assert_ne!(ingress.prog_id(), old_id);
+    assert_eq!(ingress.handle(), 1);
+    assert_eq!(ingress.priority(), 1);
+    assert_eq!(
+        hook.query(TcAttachPoint::Ingress, 1, 1)
+            .expect("failed to query ingress filter"),
+        ingress.prog_id()
+    );
+
+    let egress = hook
+        .attach(prog, TcAttachPoint::Egress, &TcOpts::default())
+        .expect("failed to attach egress filter");
+    assert_ne!(egress.handle(), 0);
+    assert_ne!(egress.priority(), 0);
+    let (handle, priority) = (egress.handle(), egress.priority());
+    drop(egress);
+    assert!(hook.query(TcAttachPoint::Egress, handle, priority).is_err());
+
+    ingress.disconnect();
+    drop(ingress);
+    assert!(hook.query(TcAttachPoint::Ingress, 1, 1).is_ok());
+    hook.detach(TcAttachPoint::Ingress, 1, 1)
+        .expect("failed to detach ingress filter");
+    assert!(hook.query(TcAttachPoint::Ingress, 1, 1).is_err());
+
+    hook.destroy().expect("failed to destroy clsact qdisc");
+}
+
+#[test]
+fn test_xdp() {
+    bump_rlimit_mlock();
+
+    let ifindex = setup_veth_netns();
+    let obj1 = get_test_object("pass.bpf.o");
+    let obj2 = get_test_object("pass.bpf.o");
+    let prog1 = obj1.prog("xdp_pass").expect("failed to find program");
+    let prog2 = obj2.prog("xdp_pass").expect("failed to find program");
+
+    let xdp = Xdp::new(ifindex);
+    assert_eq!(
+        xdp.query().expect("failed to query xdp"),
+        Default::default()
+    );
+
+    xdp.attach(prog1, XdpFlags::SKB_MODE)
+        .expect("failed to attach xdp in skb mode");
+    let info = xdp.query().expect("failed to query xdp");
+    assert_ne!(info.skb_prog_id, 0);
+    assert_eq!(info.drv_prog_id, 0);
+    let id1 = info.skb_prog_id;
+
+    assert!(xdp
+        .attach(prog2, XdpFlags::SKB_MODE | XdpFlags::UPDATE_IF_NOEXIST)
+        .is_err());
+
+    xdp.replace(prog2, prog1, XdpFlags::SKB_MODE)
+        .expect("failed to replace xdp program");
+    let id2 = xdp.query().expect("failed to query xdp").skb_prog_id;
+    assert_ne!(id2, 0);
+    assert_ne!(id2, id1);
+
+    // prog1 is no longer attached
+    let err = xdp
+        .replace(prog1, prog1, XdpFlags::SKB_MODE)
+        .expect_err("replaced a program that is not attached");
+    match &err {
+        libbpf_rs::Error::Operation { op, name, .. } => {
+            assert_eq!(*op, "replace xdp with");
+            assert_eq!(name, "xdp_pass");
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(err.errno(), Some(libc::EEXIST));
+
+    xdp.detach(XdpFlags::SKB_MODE)
+        .expect("failed to detach xdp in skb mode");
+    assert_eq!(xdp.query().expect("failed to query xdp").skb_prog_id, 0);
+
+    // veth supports native XDP
+    xdp.attach(prog1, XdpFlags::DRV_MODE)
+        .expect("failed to attach xdp in drv mode");
+    assert_eq!(xdp.query().expect("failed to query xdp").drv_prog_id, id1);
+    xdp.detach(XdpFlags::DRV_MODE)
+        .expect("failed to detach xdp in drv mode");
+    assert_eq!(
+        xdp.query().expect("failed to query xdp"),
+        Default::default()
+    );
+}
+
+/// Create a cgroup for the current test in the cgroup2 hierarchy and return its path.
+fn create_test_cgroup(name: &str) -> PathBuf {
+    let mounts = fs::read_to_string("/proc/mounts").expect("failed to read mounts");
+    let mount = mounts
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(2) == Some(&"cgroup2"))
+        .map(|fields| PathBuf::from(fields[1]))
+        .expect("cgroup2 is not mounted");
+
+    let path = mount.join(format!("{}-{}", name, std::process::id()));
+    fs::create_dir(&path).expect("failed to create cgroup");
+    path
+}
+
+#[test]
+fn test_cgroup() {
+    bump_rlimit_mlock();
+
+    let path = create_test_cgroup("libbpf-rs-test-cgroup");
+    defer! {
+        let _ = fs::remove_dir(&path);
+    }
+
+    let obj = get_test_object("cgroup.bpf.o");
+    let allow = obj.prog("skb_allow").expect("failed to find program");
+    let deny = obj.prog("skb_deny").expect("failed to find program");
+    let attach_type = || ProgramAttachType::CgroupInetIngress;
+
+    let cgroup = Cgroup::open(&path).expect("failed to open cgroup");
+    let attached = cgroup
+        .query(attach_type(), false)
+        .expect("failed to query cgroup");
+    assert!(attached.progs.is_empty());
+
+    cgroup
+        .attach(allow, attach_type(), AttachFlags::ALLOW_MULTI)
+        .expect("failed to attach program");
+    cgroup
+        .attach(deny, attach_type(), AttachFlags::ALLOW_MULTI)
+        .expect("failed to attach program");
+    let attached = cgroup
+        .query(attach_type(), false)
+        .expect("failed to query cgroup");
+    assert_eq!(attached.attach_flags, AttachFlags::ALLOW_MULTI);
+    let names: Vec<_> = attached
+        .progs
+        .iter()
+        .map(|prog| prog.name.as_str())
+        .collect();
+    assert_eq!(names, ["skb_allow", "skb_deny"]);
+
+    // The same program can only be attached once
+    assert!(cgroup
+        .attach(allow, attach_type(), AttachFlags::ALLOW_MULTI)
+        .is_err());
+
+    cgroup
+        .detach(Some(allow), attach_type())
+        .expect("failed to detach program");
+    cgroup
+        .replace(allow, deny, attach_type(), AttachFlags::ALLOW_MULTI)
+        .expect("failed to replace program");
+    let attached = Cgroup::from_fd(cgroup.fd())
+        .query(attach_type(), true)
+        .expect("failed to query effective programs");
+    let names: Vec<_> = attached
+        .progs
+        .iter()
+        .map(|prog| prog.name.as_str())
+        .collect();
+    assert_eq!(names, ["skb_allow"]);
+
+    cgroup
+        .detach(Some(allow), attach_type())
+        .expect("failed to detach program");
+    assert!(cgroup
+        .query(attach_type(), false)
+        .expect("failed to query cgroup")
+        .progs
+        .is_empty());
+    assert!(cgroup.detach(Some(allow), attach_type()).is_err());
+}